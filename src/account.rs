@@ -1,29 +1,65 @@
+use crate::currency::Currency;
+
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Clone)]
-pub struct Account {
-    pub client: u16,
+/// The funds a client holds in a single currency.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Balance {
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
+}
+
+impl Balance {
+    pub fn is_valid(&self) -> bool {
+        let expected_total = self.available + self.held;
+
+        self.total == expected_total
+    }
+}
+
+/// A client account with one balance per currency it has transacted in. A lock
+/// applies to the whole account, whatever the currency of the chargeback.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Account {
+    pub client: u16,
+    pub balances: BTreeMap<Currency, Balance>,
     pub locked: bool,
+    /// A closed account is also locked, but unlike a lock closing is final.
+    #[serde(default)]
+    pub closed: bool,
 }
 
 impl Account {
     pub fn new(client: u16) -> Self {
         Self {
             client,
-            available: Decimal::ZERO,
-            held: Decimal::ZERO,
-            total: Decimal::ZERO,
+            balances: BTreeMap::new(),
             locked: false,
+            closed: false,
         }
     }
 
+    /// The balance held in `currency`, zero if the account has never used it.
+    pub fn balance(&self, currency: Currency) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
+
+    pub fn balance_mut(&mut self, currency: Currency) -> &mut Balance {
+        self.balances.entry(currency).or_default()
+    }
+
     pub fn is_valid(&self) -> bool {
-        let expected_total = self.available + self.held;
+        self.balances.values().all(Balance::is_valid)
+    }
 
-        self.total == expected_total
+    /// Whether nothing is held in any currency, as required to close it.
+    pub fn is_empty(&self) -> bool {
+        self.balances
+            .values()
+            .all(|balance| balance.total.is_zero() && balance.held.is_zero())
     }
 
     pub fn is_available(&self) -> bool {
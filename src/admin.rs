@@ -0,0 +1,55 @@
+//! Operator actions on accounts, as opposed to client transactions.
+//!
+//! - `unlock` lifts a lock, whether it was set by a chargeback or a freeze
+//! - `freeze` locks an account by hand
+//! - `close` locks an account for good, it must hold nothing in any currency
+//!
+//! Every action names the operator who took it and why, and is kept in the
+//! engine's admin log of the client, see `Engine::admin_log`, so a lock can
+//! always be traced back.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminAction {
+    Unlock,
+    Freeze,
+    Close,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AdminEvent {
+    pub client: u16,
+    pub action: AdminAction,
+    pub operator: String,
+    pub reason: String,
+}
+
+impl AdminEvent {
+    pub fn new(
+        client: u16,
+        action: AdminAction,
+        operator: impl Into<String>,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self {
+            client,
+            action,
+            operator: operator.into(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn unlock(client: u16, operator: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::new(client, AdminAction::Unlock, operator, reason)
+    }
+
+    pub fn freeze(client: u16, operator: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::new(client, AdminAction::Freeze, operator, reason)
+    }
+
+    pub fn close(client: u16, operator: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::new(client, AdminAction::Close, operator, reason)
+    }
+}
@@ -0,0 +1,69 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// A three letter ISO 4217 currency code, always held in upper case.
+///
+/// Transactions that do not name a currency are in `Currency::USD`, the
+/// currency every account was implicitly held in before balances were split
+/// by currency.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const EUR: Currency = Currency(*b"EUR");
+    pub const GBP: Currency = Currency(*b"GBP");
+    pub const USD: Currency = Currency(*b"USD");
+
+    pub fn as_str(&self) -> &str {
+        // Only ever built from ASCII letters
+        std::str::from_utf8(&self.0).expect("currency code is ASCII")
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::USD
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code: [u8; 3] = s
+            .trim()
+            .as_bytes()
+            .try_into()
+            .map_err(|_| format!("Invalid currency '{}', expected a three letter code", s))?;
+
+        if !code.iter().all(u8::is_ascii_alphabetic) {
+            return Err(format!(
+                "Invalid currency '{}', expected a three letter code",
+                s
+            ));
+        }
+
+        Ok(Currency(code.map(|c| c.to_ascii_uppercase())))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(D::Error::custom)
+    }
+}
@@ -1,27 +1,155 @@
 use crate::account::Account;
-use crate::error::EngineError;
-use crate::transaction::{Transaction, TransactionType};
+use crate::admin::{AdminAction, AdminEvent};
+use crate::currency::Currency;
+use crate::error::{EngineError, LedgerError, SnapshotError, WalError};
+use crate::ledger::{Entry, Ledger, LedgerAccount};
+use crate::output::{AccountWriter, CsvAccountWriter};
+use crate::policy::{DisputeHold, EnginePolicy};
+use crate::snapshot::{read_snapshot, write_snapshot, SnapshotRecord};
+use crate::transaction::{StoredTransaction, Transaction, TransactionState, TransactionType};
+use crate::wal::{Wal, WalCursor, WalRecord};
+use crate::Origin;
 
 use rust_decimal::Decimal;
 use std::collections::HashMap;
-use std::io::Write;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// What an engine keeps about one client apart from its transactions, moved
+/// between engines with `Engine::take_client` and `Engine::put_client`.
+#[derive(Debug, Default)]
+pub struct ClientState {
+    account: Option<Account>,
+    history: Option<Vec<u32>>,
+    admin_log: Option<Vec<AdminEvent>>,
+}
 
 #[derive(Default)]
 pub struct Engine {
     accounts: HashMap<u16, Account>,
-    transactions: HashMap<u32, Transaction>,
+    transactions: HashMap<u32, StoredTransaction>,
+    /// Ids of the stored transactions touching each client, the sender and the
+    /// receiver of a transfer alike, in the order they were stored.
+    history: HashMap<u16, Vec<u32>>,
+    /// Operator actions taken on each client, oldest first. Kept apart from
+    /// `accounts` so the copy every transaction works on stays small.
+    admin_log: HashMap<u16, Vec<AdminEvent>>,
+    /// Every movement of funds behind the balances in `accounts`.
+    ledger: Ledger,
+    policy: EnginePolicy,
+    wal: Option<Wal>,
+    /// Write-ahead log records the state holds, counted from the first ever
+    /// logged, see `crate::wal`.
+    wal_position: u64,
+    /// The last row of input the write-ahead log holds, see `resume_point`.
+    resume_point: Option<WalCursor>,
 }
 
 impl Engine {
-    pub fn apply_transaction(&mut self, tx: Transaction) -> Result<(), EngineError> {
-        let account = self
-            .accounts
-            .entry(tx.client)
-            .or_insert(Account::new(tx.client));
+    /// Sets the rules applied to disputes from here on.
+    pub fn with_policy(mut self, policy: EnginePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> EnginePolicy {
+        self.policy
+    }
+
+    /// Puts the engine in write-ahead log mode. The transactions and admin
+    /// actions already in the log at `path` are replayed on top of the current
+    /// state, then every one accepted from here on is appended to the log and
+    /// synced before it is applied.
+    ///
+    /// The log must be replayed on top of the state it was started from, or
+    /// of a snapshot taken since, whose records are skipped. A log starting
+    /// past the current state is refused, as is, most likely, one replayed on
+    /// top of an unrelated state.
+    pub fn with_wal<P: AsRef<Path>>(mut self, path: P) -> Result<Self, WalError> {
+        let (mut wal, records) = Wal::open(path)?;
+
+        let first_line = if wal.start().is_some() { 2 } else { 1 };
+        let start = match wal.start() {
+            Some(start) => start,
+            None if records.is_empty() => {
+                wal.begin(self.wal_position)?;
+                self.wal_position
+            }
+            None => 0,
+        };
+        if start > self.wal_position {
+            return Err(WalError::StartsAhead {
+                start,
+                position: self.wal_position,
+            });
+        }
+
+        for (index, record) in records.into_iter().enumerate() {
+            // Logged before the snapshot the state was restored from
+            if start + (index as u64) < self.wal_position {
+                continue;
+            }
+
+            let result = match record {
+                WalRecord::Input { input, tx } => {
+                    self.resume_point = Some(input);
+                    self.apply_transaction(tx)
+                }
+                WalRecord::Transaction(tx) => self.apply_transaction(tx),
+                WalRecord::Admin(event) => self.apply_admin(event),
+            };
+            result.map_err(|source| WalError::Replay {
+                line: index + first_line,
+                source,
+            })?;
+            self.wal_position += 1;
+        }
+
+        self.wal = Some(wal);
+        Ok(self)
+    }
+
+    pub fn has_wal(&self) -> bool {
+        self.wal.is_some()
+    }
+
+    /// The input and row of the last transaction the write-ahead log replayed
+    /// was read from, if any. A rerun of the same input carries on after it,
+    /// see `crate::wal::ResumeFilter`.
+    pub fn resume_point(&self) -> Option<&WalCursor> {
+        self.resume_point.as_ref()
+    }
 
-        if !account.is_available() {
-            return Err(EngineError::AccountLocked(tx.client));
+    /// Empties the write-ahead log, once the state it rebuilds has been saved
+    /// elsewhere, e.g. in a snapshot.
+    pub fn truncate_wal(&mut self) -> io::Result<()> {
+        if let Some(wal) = &mut self.wal {
+            wal.truncate(self.wal_position)?;
         }
+        self.resume_point = None;
+        Ok(())
+    }
+
+    /// Applies a single transaction. Either every change the transaction implies
+    /// is committed or, on error, the engine is left exactly as it was.
+    pub fn apply_transaction(&mut self, tx: Transaction) -> Result<(), EngineError> {
+        self.apply(tx, None)
+    }
+
+    /// Like `apply_transaction`, for a transaction read from the input at
+    /// `origin`. The write-ahead log records where it was read from.
+    pub fn apply_transaction_at(
+        &mut self,
+        tx: Transaction,
+        origin: &Origin,
+    ) -> Result<(), EngineError> {
+        self.apply(tx, Some(origin))
+    }
+
+    fn apply(&mut self, tx: Transaction, origin: Option<&Origin>) -> Result<(), EngineError> {
+        // Work on a copy of the account and only write it back once every check
+        // has passed, so a rejection can never leave a half-applied update behind
+        let mut account = self.account_copy(tx.client)?;
 
         match tx.kind {
             TransactionType::Deposit | TransactionType::Withdrawal => {
@@ -29,132 +157,648 @@ impl Engine {
                     return Err(EngineError::DuplicateTransaction(tx.tx_id));
                 }
 
-                let amount = tx.amount.ok_or(EngineError::ZeroAmount(tx.tx_id))?;
-
-                match tx.kind {
-                    TransactionType::Deposit => deposit(account, amount)?,
-                    TransactionType::Withdrawal => withdraw(account, amount)?,
+                let amount = tx.amount.ok_or(EngineError::MissingAmount(tx.tx_id))?;
+                let available = LedgerAccount::Available(tx.client);
+
+                let entry = match tx.kind {
+                    TransactionType::Deposit => {
+                        deposit(&mut account, tx.currency, amount)?;
+                        posting(&tx, &tx, LedgerAccount::External, available, amount)
+                    }
+                    TransactionType::Withdrawal => {
+                        withdraw(&mut account, tx.currency, amount)?;
+                        posting(&tx, &tx, available, LedgerAccount::External, amount)
+                    }
                     _ => unreachable!(),
+                };
+
+                if let Some(wal) = &mut self.wal {
+                    wal.append(&tx, origin)
+                        .map_err(|e| EngineError::WalWrite(tx.tx_id, e))?;
+                    self.wal_position += 1;
+                }
+                self.accounts.insert(tx.client, account);
+                self.ledger.post([entry]);
+                self.store(StoredTransaction::new(tx));
+            }
+            TransactionType::Transfer => {
+                if self.transactions.contains_key(&tx.tx_id) {
+                    return Err(EngineError::DuplicateTransaction(tx.tx_id));
                 }
 
-                self.transactions.insert(tx.tx_id, tx);
+                let amount = tx.amount.ok_or(EngineError::MissingAmount(tx.tx_id))?;
+                let to = transfer_destination(&tx)?;
+                let mut destination = self.account_copy(to)?;
+
+                withdraw(&mut account, tx.currency, amount)?;
+                deposit(&mut destination, tx.currency, amount)?;
+                let entry = posting(
+                    &tx,
+                    &tx,
+                    LedgerAccount::Available(tx.client),
+                    LedgerAccount::Available(to),
+                    amount,
+                );
+
+                if let Some(wal) = &mut self.wal {
+                    wal.append(&tx, origin)
+                        .map_err(|e| EngineError::WalWrite(tx.tx_id, e))?;
+                    self.wal_position += 1;
+                }
+                self.accounts.insert(tx.client, account);
+                self.accounts.insert(to, destination);
+                self.ledger.post([entry]);
+                self.store(StoredTransaction::new(tx));
             }
             TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
-                let original = self
+                let stored = self
                     .transactions
-                    .get(&tx.tx_id)
+                    .get_mut(&tx.tx_id)
                     .ok_or(EngineError::NonExistentTransaction(tx.tx_id))?;
+                let original = &stored.tx;
 
                 if original.client != tx.client {
                     return Err(EngineError::InvalidClient(tx.client, original.client));
                 }
 
-                if original.kind == TransactionType::Withdrawal {
+                if original.kind == TransactionType::Withdrawal && !self.policy.dispute_withdrawals
+                {
                     return Err(EngineError::InvalidOperationOnWithdrawal);
                 }
 
-                match tx.kind {
-                    TransactionType::Dispute => dispute(account, original)?,
-                    TransactionType::Resolve => resolve(account, original)?,
-                    TransactionType::Chargeback => chargeback(account, original)?,
+                let next_state = stored.state.next(tx.tx_id, &tx.kind)?;
+                if tx.kind == TransactionType::Dispute {
+                    self.policy.check_dispute_window(original, &tx)?;
+                }
+
+                // A disputed transfer holds the funds where they went, with the
+                // destination, and a chargeback sends them back to the sender
+                if original.kind == TransactionType::Transfer {
+                    let to = transfer_destination(original)?;
+                    let mut destination = self
+                        .accounts
+                        .get(&to)
+                        .cloned()
+                        .unwrap_or_else(|| Account::new(to));
+
+                    ensure_available(&destination)?;
+
+                    let (held, entry) = match tx.kind {
+                        TransactionType::Dispute => {
+                            let held =
+                                dispute(&mut destination, original, self.policy.dispute_hold)?;
+                            let entry = hold_posting(&tx, original, to, held);
+                            (held, entry)
+                        }
+                        TransactionType::Resolve => {
+                            resolve(&mut destination, original, stored.held)?;
+                            (
+                                Decimal::ZERO,
+                                release_posting(&tx, original, to, stored.held),
+                            )
+                        }
+                        TransactionType::Chargeback => {
+                            reverse_transfer(
+                                &mut account,
+                                &mut destination,
+                                original,
+                                stored.held,
+                            )?;
+                            let entry = posting(
+                                &tx,
+                                original,
+                                LedgerAccount::Held(to),
+                                LedgerAccount::Available(tx.client),
+                                stored.held,
+                            );
+                            (Decimal::ZERO, entry)
+                        }
+                        _ => unreachable!(),
+                    };
+
+                    if let Some(wal) = &mut self.wal {
+                        wal.append(&tx, origin)
+                            .map_err(|e| EngineError::WalWrite(tx.tx_id, e))?;
+                        self.wal_position += 1;
+                    }
+                    self.accounts.insert(tx.client, account);
+                    self.accounts.insert(to, destination);
+                    self.ledger.post([entry]);
+                    stored.state = next_state;
+                    stored.held = held;
+
+                    return Ok(());
+                }
+
+                // Whatever the dispute held is exactly what its resolve or
+                // chargeback releases
+                let (held, entry) = match tx.kind {
+                    TransactionType::Dispute => {
+                        let held = dispute(&mut account, original, self.policy.dispute_hold)?;
+                        (held, hold_posting(&tx, original, tx.client, held))
+                    }
+                    TransactionType::Resolve => {
+                        resolve(&mut account, original, stored.held)?;
+                        (
+                            Decimal::ZERO,
+                            release_posting(&tx, original, tx.client, stored.held),
+                        )
+                    }
+                    TransactionType::Chargeback => {
+                        chargeback(&mut account, original, stored.held)?;
+                        (
+                            Decimal::ZERO,
+                            chargeback_posting(&tx, original, stored.held),
+                        )
+                    }
                     _ => unreachable!(),
+                };
+
+                if let Some(wal) = &mut self.wal {
+                    wal.append(&tx, origin)
+                        .map_err(|e| EngineError::WalWrite(tx.tx_id, e))?;
+                    self.wal_position += 1;
                 }
+                self.accounts.insert(tx.client, account);
+                self.ledger.post([entry]);
+                stored.state = next_state;
+                stored.held = held;
             }
         }
 
         Ok(())
     }
 
-    pub fn dump_accounts<W: Write>(&self, mut writer: W) {
-        // Print CSV header
-        writeln!(&mut writer, "client,available,held,total,locked").unwrap();
+    /// Keeps `stored` so it can be referenced later, indexing it under every
+    /// client it touches.
+    fn store(&mut self, stored: StoredTransaction) {
+        let tx = &stored.tx;
+        for client in std::iter::once(tx.client).chain(tx.to) {
+            self.history.entry(client).or_default().push(tx.tx_id);
+        }
+
+        self.transactions.insert(tx.tx_id, stored);
+    }
+
+    /// A copy of the account of `client`, a fresh one if it does not exist yet,
+    /// or an error if it is locked.
+    fn account_copy(&self, client: u16) -> Result<Account, EngineError> {
+        let account = self
+            .accounts
+            .get(&client)
+            .cloned()
+            .unwrap_or_else(|| Account::new(client));
+
+        ensure_available(&account)?;
+
+        Ok(account)
+    }
+
+    /// Applies an operator action to an existing account, see `crate::admin`.
+    /// The action is recorded in the client's `admin_log`. A closed account
+    /// takes no further action.
+    pub fn apply_admin(&mut self, event: AdminEvent) -> Result<(), EngineError> {
+        let mut account = self
+            .accounts
+            .get(&event.client)
+            .cloned()
+            .ok_or(EngineError::NonExistentClient(event.client))?;
+
+        if account.closed {
+            return Err(EngineError::AccountClosed(event.client));
+        }
+
+        match event.action {
+            AdminAction::Unlock => {
+                if !account.locked {
+                    return Err(EngineError::AccountNotLocked(event.client));
+                }
+                account.locked = false;
+            }
+            AdminAction::Freeze => {
+                if account.locked {
+                    return Err(EngineError::AccountLocked(event.client));
+                }
+                account.locked = true;
+            }
+            AdminAction::Close => {
+                if !account.is_empty() {
+                    return Err(EngineError::NonZeroBalance(event.client));
+                }
+                account.locked = true;
+                account.closed = true;
+            }
+        }
 
-        for (client, account) in self.accounts.iter() {
-            writeln!(
-                &mut writer,
-                "{},{},{},{},{}",
-                client,
-                account.available.round_dp(4),
-                account.held.round_dp(4),
-                account.total.round_dp(4),
-                account.locked
-            )
-            .unwrap();
+        if let Some(wal) = &mut self.wal {
+            wal.append_admin(&event)
+                .map_err(|e| EngineError::WalAdminWrite(event.client, e))?;
+            self.wal_position += 1;
         }
+        self.accounts.insert(account.client, account);
+        self.admin_log.entry(event.client).or_default().push(event);
+
+        Ok(())
     }
-}
 
-pub fn deposit(account: &mut Account, amount: Decimal) -> Result<(), EngineError> {
-    account.available += amount;
-    account.total += amount;
+    pub fn account(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
 
-    if account.total < Decimal::ZERO {
-        return Err(EngineError::InvalidTransaction {
-            message: "Total balance is negative".to_string(),
-        });
+    pub fn transaction(&self, tx_id: u32) -> Option<&StoredTransaction> {
+        self.transactions.get(&tx_id)
     }
 
-    Ok(())
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    pub fn transactions(&self) -> impl Iterator<Item = &StoredTransaction> {
+        self.transactions.values()
+    }
+
+    /// The deposits, withdrawals and transfers `client` took part in, oldest
+    /// first, with their dispute state. Incoming transfers are included.
+    /// Transactions restored from a snapshot come first, ordered by id.
+    pub fn history(&self, client: u16) -> impl Iterator<Item = &StoredTransaction> {
+        self.history
+            .get(&client)
+            .into_iter()
+            .flatten()
+            .filter_map(|tx_id| self.transactions.get(tx_id))
+    }
+
+    /// The transactions in the history of `client` that are disputed and
+    /// awaiting a resolve or chargeback.
+    pub fn open_disputes(&self, client: u16) -> impl Iterator<Item = &StoredTransaction> {
+        self.history(client)
+            .filter(|stored| stored.state == TransactionState::Disputed)
+    }
+
+    /// The operator actions taken on `client`, oldest first.
+    pub fn admin_log(&self, client: u16) -> &[AdminEvent] {
+        self.admin_log.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Checks every balance against the ledger entries behind it.
+    pub fn verify_ledger(&self) -> Result<(), LedgerError> {
+        self.ledger.verify(self.accounts.values())
+    }
+
+    /// Writes the accounts, admin log, stored transactions and ledger entries
+    /// in the versioned snapshot format, see `crate::snapshot`. Accounts,
+    /// admin actions and transactions are ordered by client and transaction id,
+    /// admin actions and entries otherwise kept in the order they were taken,
+    /// so identical state always gives an identical snapshot.
+    pub fn snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut accounts: Vec<&Account> = self.accounts.values().collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+
+        let mut admin_log: Vec<(&u16, &Vec<AdminEvent>)> = self.admin_log.iter().collect();
+        admin_log.sort_unstable_by_key(|(client, _)| **client);
+
+        let mut transactions: Vec<&StoredTransaction> = self.transactions.values().collect();
+        transactions.sort_unstable_by_key(|stored| stored.tx.tx_id);
+
+        write_snapshot(
+            writer,
+            self.wal_position,
+            accounts.into_iter(),
+            admin_log.into_iter().flat_map(|(_, events)| events),
+            transactions.into_iter(),
+            self.ledger.entries().iter(),
+        )
+    }
+
+    /// Rebuilds an engine from a snapshot written by `Engine::snapshot`,
+    /// refusing it if its balances do not match its ledger.
+    pub fn restore<R: BufRead>(reader: R) -> Result<Engine, SnapshotError> {
+        let (header, records) = read_snapshot(reader)?;
+        let mut engine = Engine {
+            wal_position: header.wal_position,
+            ..Engine::default()
+        };
+
+        for record in records {
+            match record? {
+                SnapshotRecord::Account(account) => {
+                    engine.accounts.insert(account.client, account);
+                }
+                SnapshotRecord::Admin(event) => {
+                    engine
+                        .admin_log
+                        .entry(event.client)
+                        .or_default()
+                        .push(event);
+                }
+                SnapshotRecord::Transaction(stored) => engine.store(stored),
+                SnapshotRecord::Entry(entry) => engine.ledger.post([entry]),
+            }
+        }
+
+        engine.verify_ledger()?;
+
+        Ok(engine)
+    }
+
+    /// Splits the engine into `shards` engines, sending every client and its
+    /// transactions to the engine at index `shard_for(client)`. A write-ahead
+    /// log is only kept when there is a single shard.
+    pub fn split(self, shards: usize, shard_for: impl Fn(u16) -> usize) -> Vec<Engine> {
+        if shards == 1 {
+            return vec![self];
+        }
+
+        let policy = self.policy;
+        let wal_position = self.wal_position;
+        let mut engines: Vec<Engine> = (0..shards)
+            .map(|_| Engine {
+                wal_position,
+                ..Engine::default().with_policy(policy)
+            })
+            .collect();
+
+        for (client, account) in self.accounts {
+            engines[shard_for(client)].accounts.insert(client, account);
+        }
+
+        for (tx_id, stored) in self.transactions {
+            engines[shard_for(stored.tx.client)]
+                .transactions
+                .insert(tx_id, stored);
+        }
+
+        for (client, tx_ids) in self.history {
+            engines[shard_for(client)].history.insert(client, tx_ids);
+        }
+
+        for (client, events) in self.admin_log {
+            engines[shard_for(client)].admin_log.insert(client, events);
+        }
+
+        // Every entry touches a client. Which shard keeps one touching two
+        // makes no difference, the ledger is only read once merged back
+        for entry in self.ledger.entries() {
+            let client = entry.client().expect("entry touches a client");
+            engines[shard_for(client)].ledger.post([entry.clone()]);
+        }
+
+        engines
+    }
+
+    /// Folds `other` into this engine. The two must not share any client or
+    /// transaction id, as is the case for the shards of a `ShardedEngine`.
+    pub fn merge(&mut self, other: Engine) {
+        self.accounts.extend(other.accounts);
+        self.transactions.extend(other.transactions);
+        self.history.extend(other.history);
+        self.admin_log.extend(other.admin_log);
+        self.ledger.merge(other.ledger);
+        self.wal_position = self.wal_position.max(other.wal_position);
+    }
+
+    /// Removes `client` from the engine, its account, history and admin log,
+    /// so another engine can act on it, see `crate::sharded`. The transactions
+    /// of the client stay behind.
+    pub fn take_client(&mut self, client: u16) -> ClientState {
+        ClientState {
+            account: self.accounts.remove(&client),
+            history: self.history.remove(&client),
+            admin_log: self.admin_log.remove(&client),
+        }
+    }
+
+    /// Hands `client` back, as taken by `take_client` from this or another
+    /// engine.
+    pub fn put_client(&mut self, client: u16, state: ClientState) {
+        if let Some(account) = state.account {
+            self.accounts.insert(client, account);
+        }
+        if let Some(history) = state.history {
+            self.history.insert(client, history);
+        }
+        if let Some(events) = state.admin_log {
+            self.admin_log.insert(client, events);
+        }
+    }
+
+    /// Writes every account as CSV, ordered by client id.
+    pub fn dump_accounts<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.write_accounts(&mut CsvAccountWriter::new(writer))
+    }
+
+    /// Writes every account to `writer`, ordered by client id so the output is
+    /// the same from one run to the next.
+    pub fn write_accounts(&self, writer: &mut dyn AccountWriter) -> io::Result<()> {
+        let mut accounts: Vec<&Account> = self.accounts.values().collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+
+        for account in accounts {
+            writer.write_account(account)?;
+        }
+
+        writer.finish()
+    }
 }
 
-pub fn withdraw(account: &mut Account, amount: Decimal) -> Result<(), EngineError> {
-    if account.available < amount {
-        return Err(EngineError::InvalidTransaction {
-            message: "Insufficient funds".to_string(),
-        });
+pub fn deposit(
+    account: &mut Account,
+    currency: Currency,
+    amount: Decimal,
+) -> Result<(), EngineError> {
+    if account.balance(currency).total + amount < Decimal::ZERO {
+        return Err(EngineError::NegativeBalance(account.client));
     }
 
-    account.available -= amount;
-    account.total -= amount;
+    let balance = account.balance_mut(currency);
+    balance.available += amount;
+    balance.total += amount;
 
     Ok(())
 }
 
-pub fn dispute(account: &mut Account, tx: &Transaction) -> Result<(), EngineError> {
-    let mut amount = tx.amount.ok_or(EngineError::ZeroAmount(tx.tx_id))?;
+pub fn withdraw(
+    account: &mut Account,
+    currency: Currency,
+    amount: Decimal,
+) -> Result<(), EngineError> {
+    if account.balance(currency).available < amount {
+        return Err(EngineError::InsufficientFunds(account.client));
+    }
+
+    let balance = account.balance_mut(currency);
+    balance.available -= amount;
+    balance.total -= amount;
 
-    if account.available < amount {
-        amount = account.available;
+    Ok(())
+}
+
+/// Fails if `account` takes no transactions, because it was closed or locked.
+fn ensure_available(account: &Account) -> Result<(), EngineError> {
+    if account.closed {
+        return Err(EngineError::AccountClosed(account.client));
     }
 
-    account.held += amount;
-    account.available -= amount;
+    if !account.is_available() {
+        return Err(EngineError::AccountLocked(account.client));
+    }
 
     Ok(())
 }
 
-pub fn resolve(account: &mut Account, tx: &Transaction) -> Result<(), EngineError> {
-    let mut amount = tx.amount.ok_or(EngineError::ZeroAmount(tx.tx_id))?;
+/// Returns the client credited by `tx`, which must differ from the one debited.
+fn transfer_destination(tx: &Transaction) -> Result<u16, EngineError> {
+    match tx.to {
+        Some(to) if to == tx.client => Err(EngineError::SelfTransfer(tx.tx_id)),
+        Some(to) => Ok(to),
+        None => Err(EngineError::MissingDestination(tx.tx_id)),
+    }
+}
+
+/// The ledger entry for `tx` moving `amount` from `debit` to `credit`, in the
+/// currency of `original`, the transaction `tx` acts on.
+fn posting(
+    tx: &Transaction,
+    original: &Transaction,
+    debit: LedgerAccount,
+    credit: LedgerAccount,
+    amount: Decimal,
+) -> Entry {
+    Entry::new(
+        tx.tx_id,
+        tx.kind.clone(),
+        original.currency,
+        debit,
+        credit,
+        amount,
+    )
+}
+
+/// Posts what the dispute `tx` of `original` held on `client`, taken from the
+/// available funds, or from outside for a withdrawal that has already left.
+fn hold_posting(tx: &Transaction, original: &Transaction, client: u16, held: Decimal) -> Entry {
+    let from = match original.kind {
+        TransactionType::Withdrawal => LedgerAccount::External,
+        _ => LedgerAccount::Available(client),
+    };
+
+    posting(tx, original, from, LedgerAccount::Held(client), held)
+}
+
+/// Posts the resolve `tx` releasing `held` on `client`, the reverse of
+/// `hold_posting`.
+fn release_posting(tx: &Transaction, original: &Transaction, client: u16, held: Decimal) -> Entry {
+    let to = match original.kind {
+        TransactionType::Withdrawal => LedgerAccount::External,
+        _ => LedgerAccount::Available(client),
+    };
 
-    if account.held < amount {
-        amount = account.held;
+    posting(tx, original, LedgerAccount::Held(client), to, held)
+}
+
+/// Posts the chargeback `tx`: `held` leaves the account, or is refunded to
+/// available for a withdrawal.
+fn chargeback_posting(tx: &Transaction, original: &Transaction, held: Decimal) -> Entry {
+    let to = match original.kind {
+        TransactionType::Withdrawal => LedgerAccount::Available(original.client),
+        _ => LedgerAccount::External,
+    };
+
+    posting(tx, original, LedgerAccount::Held(original.client), to, held)
+}
+
+/// Holds the amount of `tx` in the currency it was made in and returns how
+/// much was held. A deposit or transfer holds funds still in the account,
+/// `hold` deciding what happens when they are no longer all available. A
+/// withdrawal has already left the account, so its amount is held on top of
+/// the balance.
+pub fn dispute(
+    account: &mut Account,
+    tx: &Transaction,
+    hold: DisputeHold,
+) -> Result<Decimal, EngineError> {
+    let mut amount = tx.amount.ok_or(EngineError::MissingAmount(tx.tx_id))?;
+    let balance = account.balance_mut(tx.currency);
+
+    if tx.kind == TransactionType::Withdrawal {
+        balance.held += amount;
+        balance.total += amount;
+
+        return Ok(amount);
     }
 
-    account.held -= amount;
-    account.available += amount;
+    if balance.available < amount {
+        match hold {
+            DisputeHold::Partial => amount = balance.available.max(Decimal::ZERO),
+            DisputeHold::Full => {}
+            DisputeHold::Reject => return Err(EngineError::InsufficientAvailable(tx.tx_id)),
+        }
+    }
 
-    Ok(())
+    balance.held += amount;
+    balance.available -= amount;
+
+    Ok(amount)
 }
 
-pub fn chargeback(account: &mut Account, tx: &Transaction) -> Result<(), EngineError> {
-    let mut amount = tx.amount.ok_or(EngineError::ZeroAmount(tx.tx_id))?;
+/// Releases `held`, the amount the dispute of `tx` held, back to available for
+/// a deposit or transfer, and out of the account again for a withdrawal.
+pub fn resolve(account: &mut Account, tx: &Transaction, held: Decimal) -> Result<(), EngineError> {
+    let balance = account.balance_mut(tx.currency);
 
-    if account.held < amount {
-        amount = account.held;
+    balance.held -= held;
+    if tx.kind == TransactionType::Withdrawal {
+        balance.total -= held;
+    } else {
+        balance.available += held;
     }
 
-    account.held -= amount;
-    account.total -= amount;
+    Ok(())
+}
+
+/// Settles the dispute of `tx` in the client's favour and locks the account.
+/// For a deposit or transfer `held`, the amount the dispute held, leaves the
+/// account, and for a withdrawal it is refunded and made available.
+pub fn chargeback(
+    account: &mut Account,
+    tx: &Transaction,
+    held: Decimal,
+) -> Result<(), EngineError> {
+    let balance = account.balance_mut(tx.currency);
+
+    balance.held -= held;
+    if tx.kind == TransactionType::Withdrawal {
+        balance.available += held;
+    } else {
+        balance.total -= held;
+    }
 
     account.locked = true;
 
     Ok(())
 }
 
+/// Charges back a disputed transfer: `held`, the amount its dispute held, leaves
+/// `destination` and is credited back to `source`, and `destination` is locked.
+pub fn reverse_transfer(
+    source: &mut Account,
+    destination: &mut Account,
+    tx: &Transaction,
+    held: Decimal,
+) -> Result<(), EngineError> {
+    chargeback(destination, tx, held)?;
+
+    deposit(source, tx.currency, held)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::transaction::Transaction;
+    use crate::transaction::{Transaction, TransactionState};
     use std::str::FromStr;
 
     mod apply_transaction_tests {
@@ -168,9 +812,9 @@ mod tests {
             assert!(engine.apply_transaction(tx).is_ok());
 
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(100));
-            assert_eq!(account.total, Decimal::from(100));
-            assert_eq!(account.held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
             assert!(!account.locked);
 
             // Verify transaction was stored
@@ -191,9 +835,9 @@ mod tests {
             assert!(engine.apply_transaction(withdraw_tx).is_ok());
 
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(50));
-            assert_eq!(account.total, Decimal::from(50));
-            assert_eq!(account.held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(50));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(50));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
         }
 
         #[test]
@@ -212,8 +856,8 @@ mod tests {
 
             // Account should remain unchanged
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(50));
-            assert_eq!(account.total, Decimal::from(50));
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(50));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(50));
         }
 
         #[test]
@@ -230,9 +874,9 @@ mod tests {
             assert!(engine.apply_transaction(dispute_tx).is_ok());
 
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::ZERO);
-            assert_eq!(account.held, Decimal::from(100));
-            assert_eq!(account.total, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).held, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
         }
 
         #[test]
@@ -253,9 +897,9 @@ mod tests {
             assert!(engine.apply_transaction(resolve_tx).is_ok());
 
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(100));
-            assert_eq!(account.held, Decimal::ZERO);
-            assert_eq!(account.total, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
         }
 
         #[test]
@@ -276,9 +920,9 @@ mod tests {
             assert!(engine.apply_transaction(chargeback_tx).is_ok());
 
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::ZERO);
-            assert_eq!(account.held, Decimal::ZERO);
-            assert_eq!(account.total, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).total, Decimal::ZERO);
             assert!(account.locked);
         }
 
@@ -296,8 +940,8 @@ mod tests {
 
             // Account should only reflect the first transaction
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(100));
-            assert_eq!(account.total, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
         }
 
         #[test]
@@ -391,10 +1035,16 @@ mod tests {
             let account1 = engine.accounts.get(&1).unwrap();
             let account2 = engine.accounts.get(&2).unwrap();
 
-            assert_eq!(account1.available, Decimal::from(100));
-            assert_eq!(account1.total, Decimal::from(100));
-            assert_eq!(account2.available, Decimal::from(200));
-            assert_eq!(account2.total, Decimal::from(200));
+            assert_eq!(
+                account1.balance(Currency::USD).available,
+                Decimal::from(100)
+            );
+            assert_eq!(account1.balance(Currency::USD).total, Decimal::from(100));
+            assert_eq!(
+                account2.balance(Currency::USD).available,
+                Decimal::from(200)
+            );
+            assert_eq!(account2.balance(Currency::USD).total, Decimal::from(200));
         }
 
         #[test]
@@ -408,8 +1058,9 @@ mod tests {
             // Verify transaction was stored
             assert!(engine.transactions.contains_key(&42));
             let stored_tx = engine.transactions.get(&42).unwrap();
-            assert_eq!(stored_tx.client, 1);
-            assert_eq!(stored_tx.tx_id, 42);
+            assert_eq!(stored_tx.tx.client, 1);
+            assert_eq!(stored_tx.tx.tx_id, 42);
+            assert_eq!(stored_tx.state, TransactionState::Processed);
         }
 
         #[test]
@@ -434,9 +1085,9 @@ mod tests {
 
             // Verify dispute state
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(200));
-            assert_eq!(account.held, Decimal::from(1500));
-            assert_eq!(account.total, Decimal::from(1700));
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(200));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::from(1500));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(1700));
             assert!(!account.locked);
 
             // 5. Resolve the dispute
@@ -445,9 +1096,12 @@ mod tests {
 
             // Verify final state
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(1700));
-            assert_eq!(account.held, Decimal::ZERO);
-            assert_eq!(account.total, Decimal::from(1700));
+            assert_eq!(
+                account.balance(Currency::USD).available,
+                Decimal::from(1700)
+            );
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(1700));
             assert!(!account.locked);
 
             // Verify all transactions were stored
@@ -475,9 +1129,12 @@ mod tests {
 
             // Verify final state
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(1200));
-            assert_eq!(account.held, Decimal::ZERO);
-            assert_eq!(account.total, Decimal::from(1200));
+            assert_eq!(
+                account.balance(Currency::USD).available,
+                Decimal::from(1200)
+            );
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(1200));
             assert!(!account.locked);
 
             // Verify all transactions were stored
@@ -504,9 +1161,9 @@ mod tests {
 
             // Verify dispute state
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::ZERO);
-            assert_eq!(account.held, Decimal::from(250));
-            assert_eq!(account.total, Decimal::from(250));
+            assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).held, Decimal::from(250));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(250));
             assert!(!account.locked);
 
             // 4. Resolve the dispute
@@ -515,9 +1172,9 @@ mod tests {
 
             // Verify final state
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::from(250));
-            assert_eq!(account.held, Decimal::ZERO);
-            assert_eq!(account.total, Decimal::from(250));
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(250));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(250));
             assert!(!account.locked);
 
             // Verify all transactions were stored
@@ -544,9 +1201,9 @@ mod tests {
 
             // Verify dispute state
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::ZERO);
-            assert_eq!(account.held, Decimal::from(250));
-            assert_eq!(account.total, Decimal::from(250));
+            assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).held, Decimal::from(250));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(250));
             assert!(!account.locked);
 
             // 4. Chargeback the dispute
@@ -555,9 +1212,9 @@ mod tests {
 
             // Verify final state
             let account = engine.accounts.get(&1).unwrap();
-            assert_eq!(account.available, Decimal::ZERO);
-            assert_eq!(account.held, Decimal::ZERO);
-            assert_eq!(account.total, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).total, Decimal::ZERO);
             assert!(account.locked);
 
             // Verify all transactions were stored
@@ -566,15 +1223,129 @@ mod tests {
             assert!(engine.transactions.contains_key(&2));
         }
 
+        #[test]
+        fn test_dispute_lifecycle_states() {
+            let mut engine = Engine::default();
+
+            let deposit_tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(deposit_tx).is_ok());
+            assert_eq!(engine.transactions[&1].state, TransactionState::Processed);
+
+            let dispute_tx = Transaction::new_dispute(1, 1);
+            assert!(engine.apply_transaction(dispute_tx).is_ok());
+            assert_eq!(engine.transactions[&1].state, TransactionState::Disputed);
+
+            let resolve_tx = Transaction::new_resolve(1, 1);
+            assert!(engine.apply_transaction(resolve_tx).is_ok());
+            assert_eq!(engine.transactions[&1].state, TransactionState::Resolved);
+        }
+
+        #[test]
+        fn test_dispute_twice() {
+            let mut engine = Engine::default();
+
+            let deposit_tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(deposit_tx).is_ok());
+
+            let dispute_tx = Transaction::new_dispute(1, 1);
+            assert!(engine.apply_transaction(dispute_tx).is_ok());
+
+            let dispute_tx = Transaction::new_dispute(1, 1);
+            let result = engine.apply_transaction(dispute_tx);
+            assert!(matches!(result, Err(EngineError::AlreadyDisputed(1))));
+
+            // Funds should only be held once
+            let account = engine.accounts.get(&1).unwrap();
+            assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::USD).held, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
+        }
+
+        #[test]
+        fn test_resolve_without_dispute() {
+            let mut engine = Engine::default();
+
+            let deposit_tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(deposit_tx).is_ok());
+
+            let resolve_tx = Transaction::new_resolve(1, 1);
+            let result = engine.apply_transaction(resolve_tx);
+            assert!(matches!(result, Err(EngineError::NotDisputed(1))));
+
+            let account = engine.accounts.get(&1).unwrap();
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert_eq!(engine.transactions[&1].state, TransactionState::Processed);
+        }
+
+        #[test]
+        fn test_chargeback_without_dispute() {
+            let mut engine = Engine::default();
+
+            let deposit_tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(deposit_tx).is_ok());
+
+            let chargeback_tx = Transaction::new_chargeback(1, 1);
+            let result = engine.apply_transaction(chargeback_tx);
+            assert!(matches!(result, Err(EngineError::NotDisputed(1))));
+
+            let account = engine.accounts.get(&1).unwrap();
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
+            assert!(!account.locked);
+        }
+
+        #[test]
+        fn test_dispute_after_resolve() {
+            let mut engine = Engine::default();
+
+            let deposit_tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(deposit_tx).is_ok());
+
+            let dispute_tx = Transaction::new_dispute(1, 1);
+            assert!(engine.apply_transaction(dispute_tx).is_ok());
+
+            let resolve_tx = Transaction::new_resolve(1, 1);
+            assert!(engine.apply_transaction(resolve_tx).is_ok());
+
+            let dispute_tx = Transaction::new_dispute(1, 1);
+            let result = engine.apply_transaction(dispute_tx);
+            assert!(matches!(result, Err(EngineError::DisputeClosed(1))));
+
+            let account = engine.accounts.get(&1).unwrap();
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+        }
+
+        #[test]
+        fn test_resolve_after_chargeback() {
+            let mut engine = Engine::default();
+
+            let deposit_tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(deposit_tx).is_ok());
+            let deposit_tx = Transaction::new_deposit(1, 2, Decimal::from(50));
+            assert!(engine.apply_transaction(deposit_tx).is_ok());
+
+            let dispute_tx = Transaction::new_dispute(1, 1);
+            assert!(engine.apply_transaction(dispute_tx).is_ok());
+
+            let chargeback_tx = Transaction::new_chargeback(1, 1);
+            assert!(engine.apply_transaction(chargeback_tx).is_ok());
+            assert_eq!(engine.transactions[&1].state, TransactionState::ChargedBack);
+
+            // The account is now locked so nothing else can touch it
+            let resolve_tx = Transaction::new_resolve(1, 1);
+            let result = engine.apply_transaction(resolve_tx);
+            assert!(matches!(result, Err(EngineError::AccountLocked(1))));
+        }
+
         #[test]
         fn test_transaction_validation() {
             // Test that valid transactions are created correctly
             let deposit = Transaction::new_deposit(1, 1, Decimal::from(100));
-            assert!(deposit.is_valid());
             assert_eq!(deposit.amount, Some(Decimal::from(100)));
 
             let dispute = Transaction::new_dispute(1, 1);
-            assert!(dispute.is_valid());
             assert_eq!(dispute.amount, None);
         }
 
@@ -591,14 +1362,14 @@ mod tests {
 
             // Capture output in a buffer
             let mut buf = Vec::new();
-            engine.dump_accounts(&mut buf);
+            engine.dump_accounts(&mut buf).unwrap();
             let output = String::from_utf8(buf).unwrap();
 
             // Check CSV header
-            assert!(output.contains("client,available,held,total,locked"));
+            assert!(output.contains("client,currency,available,held,total,locked"));
             // Check CSV data
-            assert!(output.contains("1,100,0,100,false"));
-            assert!(output.contains("2,200,0,200,false"));
+            assert!(output.contains("1,USD,100,0,100,false"));
+            assert!(output.contains("2,USD,200,0,200,false"));
         }
 
         #[test]
@@ -611,13 +1382,13 @@ mod tests {
             engine.apply_transaction(tx).unwrap();
 
             let mut buf = Vec::new();
-            engine.dump_accounts(&mut buf);
+            engine.dump_accounts(&mut buf).unwrap();
             let output = String::from_utf8(buf).unwrap();
 
             // Check CSV header
-            assert!(output.contains("client,available,held,total,locked"));
+            assert!(output.contains("client,currency,available,held,total,locked"));
             // Check CSV data
-            assert!(output.contains("1,42.0001,0,42.0001,false"));
+            assert!(output.contains("1,USD,42.0001,0,42.0001,false"));
         }
 
         #[test]
@@ -632,17 +1403,957 @@ mod tests {
             }
 
             let mut buf = Vec::new();
-            engine.dump_accounts(&mut buf);
+            engine.dump_accounts(&mut buf).unwrap();
             let output = String::from_utf8(buf).unwrap();
 
             // Check CSV header
-            assert!(output.contains("client,available,held,total,locked"));
+            assert!(output.contains("client,currency,available,held,total,locked"));
 
             // Check CSV data for each account
             for i in 1..=20 {
-                let expected = format!("{},{},0,{},false", i, i * 10, i * 10);
+                let expected = format!("{},USD,{},0,{},false", i, i * 10, i * 10);
                 assert!(output.contains(&expected), "Missing: {}", expected);
             }
         }
     }
+
+    mod dump_accounts_tests {
+        use super::*;
+
+        #[test]
+        fn test_dump_accounts_sorted_by_client() {
+            let mut engine = Engine::default();
+            for client in [7, 3, 65535, 1, 42] {
+                let tx = Transaction::new_deposit(client, client as u32, Decimal::from(client));
+                engine.apply_transaction(tx).unwrap();
+            }
+
+            let mut buf = Vec::new();
+            engine.dump_accounts(&mut buf).unwrap();
+            let output = String::from_utf8(buf).unwrap();
+
+            assert_eq!(
+                output,
+                "client,currency,available,held,total,locked\n\
+                 1,USD,1,0,1,false\n\
+                 3,USD,3,0,3,false\n\
+                 7,USD,7,0,7,false\n\
+                 42,USD,42,0,42,false\n\
+                 65535,USD,65535,0,65535,false\n"
+            );
+        }
+
+        #[test]
+        fn test_dump_accounts_empty_engine() {
+            let engine = Engine::default();
+
+            let mut buf = Vec::new();
+            engine.dump_accounts(&mut buf).unwrap();
+
+            assert_eq!(
+                String::from_utf8(buf).unwrap(),
+                "client,currency,available,held,total,locked\n"
+            );
+        }
+
+        #[test]
+        fn test_dump_accounts_propagates_write_errors() {
+            struct FailingWriter;
+
+            impl Write for FailingWriter {
+                fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                    Err(io::Error::other("disk full"))
+                }
+
+                fn flush(&mut self) -> io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            let mut engine = Engine::default();
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(1));
+            engine.apply_transaction(tx).unwrap();
+
+            assert!(engine.dump_accounts(FailingWriter).is_err());
+        }
+    }
+
+    mod atomicity_tests {
+        use super::*;
+
+        type ErrorMatcher = fn(&EngineError) -> bool;
+
+        /// Builds an engine with some history to reject transactions against:
+        /// - client 1: deposits tx 1 (100) and tx 3 (50, disputed), withdraws tx 2 (30)
+        ///   and has tx 4 (10) deposited, disputed and resolved
+        /// - client 2: deposits tx 10 (20) which is charged back, locking the account
+        fn engine_with_history() -> Engine {
+            let mut engine = Engine::default();
+
+            let txs = vec![
+                Transaction::new_deposit(1, 1, Decimal::from(100)),
+                Transaction::new_withdrawal(1, 2, Decimal::from(30)),
+                Transaction::new_deposit(1, 3, Decimal::from(50)),
+                Transaction::new_dispute(1, 3),
+                Transaction::new_deposit(1, 4, Decimal::from(10)),
+                Transaction::new_dispute(1, 4),
+                Transaction::new_resolve(1, 4),
+                Transaction::new_deposit(2, 10, Decimal::from(20)),
+                Transaction::new_dispute(2, 10),
+                Transaction::new_chargeback(2, 10),
+            ];
+            for tx in txs {
+                engine.apply_transaction(tx).unwrap();
+            }
+
+            engine
+        }
+
+        /// Applies `tx`, asserts that it is rejected and that neither the accounts
+        /// nor the transaction store changed, then returns the error.
+        fn assert_rejected_unchanged(engine: &mut Engine, tx: Transaction) -> EngineError {
+            let accounts = engine.accounts.clone();
+            let transactions = engine.transactions.clone();
+
+            let err = engine
+                .apply_transaction(tx)
+                .expect_err("transaction should have been rejected");
+
+            assert_eq!(
+                engine.accounts, accounts,
+                "accounts changed after {:?}",
+                err
+            );
+            assert_eq!(
+                engine.transactions, transactions,
+                "transactions changed after {:?}",
+                err
+            );
+
+            err
+        }
+
+        #[test]
+        fn test_every_rejection_leaves_engine_unchanged() {
+            let cases: Vec<(Transaction, ErrorMatcher)> = vec![
+                (Transaction::new_deposit(2, 20, Decimal::from(5)), |e| {
+                    matches!(e, EngineError::AccountLocked(2))
+                }),
+                (Transaction::new_deposit(1, 1, Decimal::from(5)), |e| {
+                    matches!(e, EngineError::DuplicateTransaction(1))
+                }),
+                (Transaction::new_dispute(3, 1), |e| {
+                    matches!(e, EngineError::InvalidClient(3, 1))
+                }),
+                (Transaction::new_dispute(1, 2), |e| {
+                    matches!(e, EngineError::InvalidOperationOnWithdrawal)
+                }),
+                (Transaction::new_dispute(3, 999), |e| {
+                    matches!(e, EngineError::NonExistentTransaction(999))
+                }),
+                (
+                    Transaction {
+                        client: 3,
+                        tx_id: 30,
+                        kind: TransactionType::Deposit,
+                        amount: None,
+                        currency: Currency::default(),
+                        to: None,
+                        timestamp: None,
+                    },
+                    |e| matches!(e, EngineError::MissingAmount(30)),
+                ),
+                (
+                    Transaction::new_withdrawal(1, 5, Decimal::from(1000)),
+                    |e| matches!(e, EngineError::InsufficientFunds(1)),
+                ),
+                (Transaction::new_deposit(3, 31, Decimal::from(-5)), |e| {
+                    matches!(e, EngineError::NegativeBalance(3))
+                }),
+                (Transaction::new_dispute(1, 3), |e| {
+                    matches!(e, EngineError::AlreadyDisputed(3))
+                }),
+                (Transaction::new_resolve(1, 1), |e| {
+                    matches!(e, EngineError::NotDisputed(1))
+                }),
+                (Transaction::new_dispute(1, 4), |e| {
+                    matches!(e, EngineError::DisputeClosed(4))
+                }),
+            ];
+
+            for (tx, expected) in cases {
+                let mut engine = engine_with_history();
+                let description = format!("{:?}", tx);
+
+                let err = assert_rejected_unchanged(&mut engine, tx);
+                assert!(
+                    expected(&err),
+                    "unexpected error {:?} for {}",
+                    err,
+                    description
+                );
+            }
+        }
+
+        #[test]
+        fn test_rejected_transaction_does_not_create_account() {
+            let mut engine = Engine::default();
+
+            let err = assert_rejected_unchanged(&mut engine, Transaction::new_dispute(7, 1));
+            assert!(matches!(err, EngineError::NonExistentTransaction(1)));
+            assert!(!engine.accounts.contains_key(&7));
+
+            let err = assert_rejected_unchanged(
+                &mut engine,
+                Transaction::new_withdrawal(7, 2, Decimal::from(1)),
+            );
+            assert!(matches!(err, EngineError::InsufficientFunds(7)));
+            assert!(!engine.accounts.contains_key(&7));
+        }
+
+        #[test]
+        fn test_negative_deposit_leaves_balance_untouched() {
+            let mut engine = engine_with_history();
+
+            let err = assert_rejected_unchanged(
+                &mut engine,
+                Transaction::new_deposit(1, 6, Decimal::from(-1000)),
+            );
+            assert!(matches!(err, EngineError::NegativeBalance(1)));
+
+            let account = engine.accounts.get(&1).unwrap();
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(80));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::from(50));
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(130));
+        }
+    }
+
+    mod currency_tests {
+        use super::*;
+
+        #[test]
+        fn test_balances_are_kept_per_currency() {
+            let mut engine = Engine::default();
+
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+            let tx = Transaction::new_deposit(1, 2, Decimal::from(50)).with_currency(Currency::EUR);
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            // Only 50 EUR are available, the USD balance does not count
+            let tx =
+                Transaction::new_withdrawal(1, 3, Decimal::from(80)).with_currency(Currency::EUR);
+            assert!(engine.apply_transaction(tx).is_err());
+
+            let account = engine.account(1).unwrap();
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+            assert_eq!(account.balance(Currency::EUR).available, Decimal::from(50));
+            assert_eq!(account.balance(Currency::GBP), Default::default());
+        }
+
+        #[test]
+        fn test_dispute_acts_on_currency_of_referenced_transaction() {
+            let mut engine = Engine::default();
+
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+            let tx = Transaction::new_deposit(1, 2, Decimal::from(50)).with_currency(Currency::GBP);
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            // The dispute row carries no currency, the deposit it references does
+            assert!(engine
+                .apply_transaction(Transaction::new_dispute(1, 2))
+                .is_ok());
+
+            let account = engine.account(1).unwrap();
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::GBP).available, Decimal::ZERO);
+            assert_eq!(account.balance(Currency::GBP).held, Decimal::from(50));
+
+            assert!(engine
+                .apply_transaction(Transaction::new_chargeback(1, 2))
+                .is_ok());
+
+            let account = engine.account(1).unwrap();
+            assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
+            assert_eq!(account.balance(Currency::GBP).total, Decimal::ZERO);
+            assert!(account.locked);
+            assert!(account.is_valid());
+        }
+    }
+
+    mod transfer_tests {
+        use super::*;
+
+        fn funded_engine() -> Engine {
+            let mut engine = Engine::default();
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+            engine
+        }
+
+        #[test]
+        fn test_transfer_moves_funds() {
+            let mut engine = funded_engine();
+
+            let tx = Transaction::new_transfer(1, 2, 2, Decimal::from(40));
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            let source = engine.account(1).unwrap();
+            let destination = engine.account(2).unwrap();
+            assert_eq!(source.balance(Currency::USD).total, Decimal::from(60));
+            assert_eq!(
+                destination.balance(Currency::USD).available,
+                Decimal::from(40)
+            );
+            assert!(engine.transaction(2).is_some());
+        }
+
+        #[test]
+        fn test_rejected_transfer_changes_neither_side() {
+            let mut engine = funded_engine();
+            let tx = Transaction::new_deposit(2, 2, Decimal::from(5));
+            assert!(engine.apply_transaction(tx).is_ok());
+            let tx = Transaction::new_deposit(3, 3, Decimal::from(5));
+            assert!(engine.apply_transaction(tx).is_ok());
+            assert!(engine
+                .apply_transaction(Transaction::new_dispute(3, 3))
+                .is_ok());
+            assert!(engine
+                .apply_transaction(Transaction::new_chargeback(3, 3))
+                .is_ok());
+
+            let rejected = [
+                (
+                    Transaction::new_transfer(1, 2, 10, Decimal::from(500)),
+                    "insufficient_funds",
+                ),
+                (
+                    Transaction::new_transfer(1, 3, 11, Decimal::from(10)),
+                    "account_locked",
+                ),
+                (
+                    Transaction::new_transfer(3, 1, 12, Decimal::from(1)),
+                    "account_locked",
+                ),
+                (
+                    Transaction::new_transfer(1, 1, 13, Decimal::from(10)),
+                    "self_transfer",
+                ),
+                (
+                    Transaction::new_transfer(1, 2, 2, Decimal::from(10)),
+                    "duplicate_transaction",
+                ),
+            ];
+
+            for (tx, code) in rejected {
+                let err = engine.apply_transaction(tx).unwrap_err();
+                assert_eq!(err.code(), code);
+            }
+
+            assert_eq!(
+                engine.account(1).unwrap().balance(Currency::USD).total,
+                Decimal::from(100)
+            );
+            assert_eq!(
+                engine.account(2).unwrap().balance(Currency::USD).total,
+                Decimal::from(5)
+            );
+            assert!(engine.transaction(10).is_none());
+        }
+
+        #[test]
+        fn test_disputed_transfer_is_held_by_destination() {
+            let mut engine = funded_engine();
+            let tx = Transaction::new_transfer(1, 2, 2, Decimal::from(40));
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            // Only the sender can dispute the transfer
+            let err = engine
+                .apply_transaction(Transaction::new_dispute(2, 2))
+                .unwrap_err();
+            assert!(matches!(err, EngineError::InvalidClient(2, 1)));
+
+            assert!(engine
+                .apply_transaction(Transaction::new_dispute(1, 2))
+                .is_ok());
+            let destination = engine.account(2).unwrap().balance(Currency::USD);
+            assert_eq!(destination.available, Decimal::ZERO);
+            assert_eq!(destination.held, Decimal::from(40));
+
+            assert!(engine
+                .apply_transaction(Transaction::new_resolve(1, 2))
+                .is_ok());
+            let destination = engine.account(2).unwrap().balance(Currency::USD);
+            assert_eq!(destination.available, Decimal::from(40));
+            assert_eq!(destination.held, Decimal::ZERO);
+            assert_eq!(
+                engine.account(1).unwrap().balance(Currency::USD).total,
+                Decimal::from(60)
+            );
+        }
+
+        #[test]
+        fn test_transfer_chargeback_returns_funds_to_sender() {
+            let mut engine = funded_engine();
+            let tx = Transaction::new_transfer(1, 2, 2, Decimal::from(40));
+            assert!(engine.apply_transaction(tx).is_ok());
+            assert!(engine
+                .apply_transaction(Transaction::new_dispute(1, 2))
+                .is_ok());
+            assert!(engine
+                .apply_transaction(Transaction::new_chargeback(1, 2))
+                .is_ok());
+
+            let source = engine.account(1).unwrap();
+            assert_eq!(source.balance(Currency::USD).available, Decimal::from(100));
+            assert!(!source.locked);
+
+            let destination = engine.account(2).unwrap();
+            assert_eq!(destination.balance(Currency::USD).total, Decimal::ZERO);
+            assert!(destination.locked);
+        }
+    }
+
+    mod admin_tests {
+        use super::*;
+
+        fn charged_back_engine() -> Engine {
+            let mut engine = Engine::default();
+            for tx in [
+                Transaction::new_deposit(1, 1, Decimal::from(100)),
+                Transaction::new_deposit(1, 2, Decimal::from(30)),
+                Transaction::new_dispute(1, 2),
+                Transaction::new_chargeback(1, 2),
+            ] {
+                assert!(engine.apply_transaction(tx).is_ok());
+            }
+            engine
+        }
+
+        #[test]
+        fn test_unlock_reverses_chargeback_lock() {
+            let mut engine = charged_back_engine();
+
+            let event = AdminEvent::unlock(1, "alice", "chargeback was a mistake");
+            assert!(engine.apply_admin(event.clone()).is_ok());
+
+            let account = engine.account(1).unwrap();
+            assert!(!account.locked);
+            assert_eq!(engine.admin_log(1), [event]);
+
+            let tx = Transaction::new_withdrawal(1, 3, Decimal::from(10));
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            let result = engine.apply_admin(AdminEvent::unlock(1, "alice", "again"));
+            assert!(matches!(result, Err(EngineError::AccountNotLocked(1))));
+        }
+
+        #[test]
+        fn test_freeze_locks_account() {
+            let mut engine = Engine::default();
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            assert!(engine
+                .apply_admin(AdminEvent::freeze(1, "bob", "suspected fraud"))
+                .is_ok());
+
+            let tx = Transaction::new_deposit(1, 2, Decimal::from(1));
+            let result = engine.apply_transaction(tx);
+            assert!(matches!(result, Err(EngineError::AccountLocked(1))));
+
+            let result = engine.apply_admin(AdminEvent::freeze(1, "bob", "again"));
+            assert!(matches!(result, Err(EngineError::AccountLocked(1))));
+            assert_eq!(engine.admin_log(1).len(), 1);
+
+            let result = engine.apply_admin(AdminEvent::freeze(9, "bob", "unknown"));
+            assert!(matches!(result, Err(EngineError::NonExistentClient(9))));
+        }
+
+        #[test]
+        fn test_close_requires_zero_balance_and_is_final() {
+            let mut engine = Engine::default();
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            let result = engine.apply_admin(AdminEvent::close(1, "carol", "client request"));
+            assert!(matches!(result, Err(EngineError::NonZeroBalance(1))));
+            assert!(!engine.account(1).unwrap().locked);
+
+            let tx = Transaction::new_withdrawal(1, 2, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+            assert!(engine
+                .apply_admin(AdminEvent::close(1, "carol", "client request"))
+                .is_ok());
+
+            let account = engine.account(1).unwrap();
+            assert!(account.locked && account.closed);
+
+            let tx = Transaction::new_deposit(1, 3, Decimal::from(1));
+            let result = engine.apply_transaction(tx);
+            assert!(matches!(result, Err(EngineError::AccountClosed(1))));
+
+            let tx = Transaction::new_deposit(2, 4, Decimal::from(1));
+            assert!(engine.apply_transaction(tx).is_ok());
+            let tx = Transaction::new_transfer(2, 1, 5, Decimal::from(1));
+            let result = engine.apply_transaction(tx);
+            assert!(matches!(result, Err(EngineError::AccountClosed(1))));
+
+            let result = engine.apply_admin(AdminEvent::unlock(1, "carol", "reopen"));
+            assert!(matches!(result, Err(EngineError::AccountClosed(1))));
+        }
+    }
+
+    mod policy_tests {
+        use super::*;
+        use crate::policy::{days, UntimedDisputes, DEFAULT_DISPUTE_WINDOW_DAYS};
+
+        /// Deposits 100, withdraws 60 of it and disputes the deposit.
+        fn dispute_spent_deposit(policy: EnginePolicy) -> (Engine, Result<(), EngineError>) {
+            let mut engine = Engine::default().with_policy(policy);
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+            let tx = Transaction::new_withdrawal(1, 2, Decimal::from(60));
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            let result = engine.apply_transaction(Transaction::new_dispute(1, 1));
+            (engine, result)
+        }
+
+        fn policy(dispute_hold: DisputeHold) -> EnginePolicy {
+            EnginePolicy {
+                dispute_hold,
+                ..EnginePolicy::default()
+            }
+        }
+
+        #[test]
+        fn test_partial_hold() {
+            let (engine, result) = dispute_spent_deposit(policy(DisputeHold::Partial));
+            assert!(result.is_ok());
+
+            let balance = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(balance.available, Decimal::ZERO);
+            assert_eq!(balance.held, Decimal::from(40));
+        }
+
+        #[test]
+        fn test_full_hold_allows_negative_available() {
+            let (mut engine, result) = dispute_spent_deposit(policy(DisputeHold::Full));
+            assert!(result.is_ok());
+
+            let balance = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(balance.available, Decimal::from(-60));
+            assert_eq!(balance.held, Decimal::from(100));
+            assert_eq!(balance.total, Decimal::from(40));
+
+            assert!(engine
+                .apply_transaction(Transaction::new_resolve(1, 1))
+                .is_ok());
+            let balance = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(balance.available, Decimal::from(40));
+            assert_eq!(balance.held, Decimal::ZERO);
+        }
+
+        #[test]
+        fn test_reject_hold_rejects_dispute() {
+            let (engine, result) = dispute_spent_deposit(policy(DisputeHold::Reject));
+            assert!(matches!(result, Err(EngineError::InsufficientAvailable(1))));
+
+            let balance = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(balance.available, Decimal::from(40));
+            assert_eq!(balance.held, Decimal::ZERO);
+            assert_eq!(
+                engine.transaction(1).unwrap().state,
+                TransactionState::Processed
+            );
+        }
+
+        #[test]
+        fn test_disputed_withdrawal_is_refunded_on_chargeback() {
+            let policy = EnginePolicy {
+                dispute_withdrawals: true,
+                ..EnginePolicy::default()
+            };
+            let mut engine = Engine::default().with_policy(policy);
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+            let tx = Transaction::new_withdrawal(1, 2, Decimal::from(30));
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            assert!(engine
+                .apply_transaction(Transaction::new_dispute(1, 2))
+                .is_ok());
+            let balance = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(balance.available, Decimal::from(70));
+            assert_eq!(balance.held, Decimal::from(30));
+            assert_eq!(balance.total, Decimal::from(100));
+
+            assert!(engine
+                .apply_transaction(Transaction::new_chargeback(1, 2))
+                .is_ok());
+            let account = engine.account(1).unwrap();
+            assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+            assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+            assert!(account.locked);
+            assert!(account.is_valid());
+        }
+
+        #[test]
+        fn test_resolved_withdrawal_dispute_drops_hold() {
+            let policy = EnginePolicy {
+                dispute_withdrawals: true,
+                ..EnginePolicy::default()
+            };
+            let mut engine = Engine::default().with_policy(policy);
+            let tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            assert!(engine.apply_transaction(tx).is_ok());
+            let tx = Transaction::new_withdrawal(1, 2, Decimal::from(30));
+            assert!(engine.apply_transaction(tx).is_ok());
+            assert!(engine
+                .apply_transaction(Transaction::new_dispute(1, 2))
+                .is_ok());
+            assert!(engine
+                .apply_transaction(Transaction::new_resolve(1, 2))
+                .is_ok());
+
+            let balance = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(balance.available, Decimal::from(70));
+            assert_eq!(balance.held, Decimal::ZERO);
+            assert_eq!(balance.total, Decimal::from(70));
+        }
+
+        /// Deposits 100 at `deposited_at` and disputes it at `disputed_at`.
+        fn dispute_at(
+            policy: EnginePolicy,
+            deposited_at: Option<u64>,
+            disputed_at: Option<u64>,
+        ) -> (Engine, Result<(), EngineError>) {
+            let mut engine = Engine::default().with_policy(policy);
+            let mut tx = Transaction::new_deposit(1, 1, Decimal::from(100));
+            tx.timestamp = deposited_at;
+            assert!(engine.apply_transaction(tx).is_ok());
+
+            let mut tx = Transaction::new_dispute(1, 1);
+            tx.timestamp = disputed_at;
+            let result = engine.apply_transaction(tx);
+            (engine, result)
+        }
+
+        #[test]
+        fn test_dispute_window() {
+            let deposited_at = 1_700_000_000;
+            let window = days(DEFAULT_DISPUTE_WINDOW_DAYS).unwrap().as_secs();
+
+            let (engine, result) = dispute_at(
+                EnginePolicy::default(),
+                Some(deposited_at),
+                Some(deposited_at + window),
+            );
+            assert!(result.is_ok());
+            assert_eq!(
+                engine.account(1).unwrap().balance(Currency::USD).held,
+                Decimal::from(100)
+            );
+
+            let (engine, result) = dispute_at(
+                EnginePolicy::default(),
+                Some(deposited_at),
+                Some(deposited_at + window + 1),
+            );
+            assert!(matches!(result, Err(EngineError::DisputeWindowExpired(1))));
+            assert_eq!(
+                engine.transaction(1).unwrap().state,
+                TransactionState::Processed
+            );
+            assert_eq!(
+                engine.account(1).unwrap().balance(Currency::USD).held,
+                Decimal::ZERO
+            );
+
+            // Out of order timestamps are not held against the client
+            let (_, result) = dispute_at(EnginePolicy::default(), Some(deposited_at), Some(0));
+            assert!(result.is_ok());
+
+            let policy = EnginePolicy {
+                dispute_window: None,
+                ..EnginePolicy::default()
+            };
+            let (_, result) = dispute_at(policy, Some(0), Some(u64::MAX));
+            assert!(result.is_ok());
+        }
+
+        #[test]
+        fn test_untimed_disputes() {
+            for timestamps in [(None, Some(5)), (Some(5), None), (None, None)] {
+                let (_, result) = dispute_at(EnginePolicy::default(), timestamps.0, timestamps.1);
+                assert!(result.is_ok());
+
+                let policy = EnginePolicy {
+                    untimed_disputes: UntimedDisputes::Reject,
+                    ..EnginePolicy::default()
+                };
+                let (_, result) = dispute_at(policy, timestamps.0, timestamps.1);
+                assert!(matches!(result, Err(EngineError::MissingTimestamp(1))));
+            }
+        }
+
+        #[test]
+        fn test_dispute_window_days_overflow() {
+            assert_eq!(days(1).unwrap().as_secs(), 86_400);
+            assert_eq!(days(999_999_999_999_999_999), None);
+            assert_eq!(days(u64::MAX), None);
+        }
+
+        #[test]
+        fn test_dispute_window_does_not_apply_to_resolves() {
+            let (mut engine, result) = dispute_at(EnginePolicy::default(), Some(0), Some(10));
+            assert!(result.is_ok());
+
+            let tx = Transaction::new_resolve(1, 1).with_timestamp(u64::MAX);
+            assert!(engine.apply_transaction(tx).is_ok());
+        }
+
+        #[test]
+        fn test_split_keeps_policy() {
+            let policy = EnginePolicy {
+                dispute_hold: DisputeHold::Reject,
+                dispute_withdrawals: true,
+                ..EnginePolicy::default()
+            };
+
+            for engine in Engine::default()
+                .with_policy(policy)
+                .split(3, |c| c as usize % 3)
+            {
+                assert_eq!(engine.policy(), policy);
+            }
+        }
+    }
+
+    mod held_tracking_tests {
+        use super::*;
+
+        #[test]
+        fn test_each_dispute_releases_what_it_held() {
+            let mut engine = Engine::default();
+            for tx in [
+                Transaction::new_deposit(1, 1, Decimal::from(100)),
+                Transaction::new_deposit(1, 2, Decimal::from(50)),
+                Transaction::new_withdrawal(1, 3, Decimal::from(120)),
+                // Only 30 is left to hold for the first dispute, nothing for the second
+                Transaction::new_dispute(1, 1),
+                Transaction::new_dispute(1, 2),
+                Transaction::new_deposit(1, 4, Decimal::from(100)),
+            ] {
+                assert!(engine.apply_transaction(tx).is_ok());
+            }
+
+            assert_eq!(engine.transaction(1).unwrap().held, Decimal::from(30));
+            assert_eq!(engine.transaction(2).unwrap().held, Decimal::ZERO);
+
+            // Resolving the second dispute must not release the first one's hold
+            assert!(engine
+                .apply_transaction(Transaction::new_resolve(1, 2))
+                .is_ok());
+            let balance = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(balance.available, Decimal::from(100));
+            assert_eq!(balance.held, Decimal::from(30));
+
+            assert!(engine
+                .apply_transaction(Transaction::new_chargeback(1, 1))
+                .is_ok());
+            let balance = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(balance.available, Decimal::from(100));
+            assert_eq!(balance.held, Decimal::ZERO);
+            assert_eq!(balance.total, Decimal::from(100));
+            assert_eq!(engine.transaction(1).unwrap().held, Decimal::ZERO);
+        }
+
+        #[test]
+        fn test_transfer_chargeback_returns_what_was_held() {
+            let mut engine = Engine::default();
+            for tx in [
+                Transaction::new_deposit(1, 1, Decimal::from(100)),
+                Transaction::new_transfer(1, 2, 2, Decimal::from(40)),
+                Transaction::new_withdrawal(2, 3, Decimal::from(25)),
+                Transaction::new_dispute(1, 2),
+                Transaction::new_chargeback(1, 2),
+            ] {
+                assert!(engine.apply_transaction(tx).is_ok());
+            }
+
+            // Only the 15 left with the destination could be held and returned
+            let source = engine.account(1).unwrap().balance(Currency::USD);
+            assert_eq!(source.available, Decimal::from(75));
+            let destination = engine.account(2).unwrap().balance(Currency::USD);
+            assert_eq!(destination.total, Decimal::ZERO);
+            assert_eq!(destination.held, Decimal::ZERO);
+        }
+    }
+
+    mod ledger_tests {
+        use super::*;
+        use crate::policy::EnginePolicy;
+
+        #[test]
+        fn test_ledger_matches_balances_after_every_transaction() {
+            let mut engine = Engine::default().with_policy(EnginePolicy {
+                dispute_hold: DisputeHold::Partial,
+                dispute_withdrawals: true,
+                ..EnginePolicy::default()
+            });
+
+            for tx in [
+                Transaction::new_deposit(1, 1, Decimal::from(100)),
+                Transaction::new_deposit(1, 2, Decimal::from(30)).with_currency(Currency::EUR),
+                Transaction::new_withdrawal(1, 3, Decimal::from(70)),
+                // Only 30 of the 100 is left to hold
+                Transaction::new_dispute(1, 1),
+                Transaction::new_resolve(1, 1),
+                Transaction::new_dispute(1, 3),
+                Transaction::new_chargeback(1, 3),
+                Transaction::new_deposit(2, 4, Decimal::from(50)),
+                Transaction::new_transfer(2, 3, 5, Decimal::from(20)),
+                Transaction::new_dispute(2, 5),
+                Transaction::new_chargeback(2, 5),
+                Transaction::new_deposit(4, 6, Decimal::from(10)),
+                Transaction::new_dispute(4, 6),
+                Transaction::new_chargeback(4, 6),
+            ] {
+                let description = format!("{:?}", tx);
+                assert!(engine.apply_transaction(tx).is_ok(), "{}", description);
+                assert!(engine.verify_ledger().is_ok(), "after {}", description);
+            }
+
+            // Rejected transactions post nothing
+            let posted = engine.ledger().entries().len();
+            assert!(engine
+                .apply_transaction(Transaction::new_withdrawal(2, 7, Decimal::from(1000)))
+                .is_err());
+            assert_eq!(engine.ledger().entries().len(), posted);
+
+            // The outside world holds what every client is owed, per currency
+            assert_eq!(
+                engine
+                    .ledger()
+                    .balance(LedgerAccount::External, Currency::USD),
+                Decimal::from(150)
+            );
+            assert_eq!(
+                engine
+                    .ledger()
+                    .balance(LedgerAccount::External, Currency::EUR),
+                Decimal::from(30)
+            );
+        }
+
+        #[test]
+        fn test_dispute_posts_what_it_held() {
+            let mut engine = Engine::default();
+            for tx in [
+                Transaction::new_deposit(1, 1, Decimal::from(100)),
+                Transaction::new_withdrawal(1, 2, Decimal::from(60)),
+                Transaction::new_dispute(1, 1),
+            ] {
+                assert!(engine.apply_transaction(tx).is_ok());
+            }
+
+            let entries = engine.ledger().entries();
+            assert_eq!(entries.len(), 3);
+            assert_eq!(
+                entries[2],
+                Entry::new(
+                    1,
+                    TransactionType::Dispute,
+                    Currency::USD,
+                    LedgerAccount::Available(1),
+                    LedgerAccount::Held(1),
+                    Decimal::from(40),
+                )
+            );
+        }
+
+        #[test]
+        fn test_verify_catches_a_balance_without_entries() {
+            let mut engine = Engine::default();
+            assert!(engine
+                .apply_transaction(Transaction::new_deposit(1, 1, Decimal::from(10)))
+                .is_ok());
+
+            let balance = engine
+                .accounts
+                .get_mut(&1)
+                .unwrap()
+                .balance_mut(Currency::USD);
+            balance.available += Decimal::ONE;
+            balance.total += Decimal::ONE;
+
+            let err = engine.verify_ledger().unwrap_err();
+            assert_eq!(err.account, LedgerAccount::Available(1));
+            assert_eq!(err.ledger, Decimal::from(10));
+            assert_eq!(err.balance, Decimal::from(11));
+        }
+    }
+
+    mod history_tests {
+        use super::*;
+
+        fn tx_ids<'a>(stored: impl Iterator<Item = &'a StoredTransaction>) -> Vec<u32> {
+            stored.map(|stored| stored.tx.tx_id).collect()
+        }
+
+        #[test]
+        fn test_history_is_kept_per_client_in_order() {
+            let mut engine = Engine::default();
+            for tx in [
+                Transaction::new_deposit(1, 9, Decimal::from(100)),
+                Transaction::new_deposit(2, 3, Decimal::from(50)),
+                Transaction::new_transfer(2, 1, 5, Decimal::from(20)),
+                Transaction::new_withdrawal(1, 1, Decimal::from(10)),
+                Transaction::new_dispute(1, 9),
+                Transaction::new_dispute(2, 5),
+                Transaction::new_resolve(2, 5),
+            ] {
+                assert!(engine.apply_transaction(tx).is_ok());
+            }
+
+            // Both sides of a transfer see it, disputes only change state
+            assert_eq!(tx_ids(engine.history(1)), [9, 5, 1]);
+            assert_eq!(tx_ids(engine.history(2)), [3, 5]);
+            assert_eq!(tx_ids(engine.history(7)), Vec::<u32>::new());
+
+            assert_eq!(tx_ids(engine.open_disputes(1)), [9]);
+            assert_eq!(tx_ids(engine.open_disputes(2)), Vec::<u32>::new());
+            assert_eq!(
+                engine.history(1).next().unwrap().state,
+                TransactionState::Disputed
+            );
+        }
+
+        #[test]
+        fn test_history_survives_split_and_snapshot() {
+            let mut engine = Engine::default();
+            for tx in [
+                Transaction::new_deposit(1, 1, Decimal::from(10)),
+                Transaction::new_deposit(2, 2, Decimal::from(10)),
+                Transaction::new_deposit(1, 3, Decimal::from(10)),
+            ] {
+                assert!(engine.apply_transaction(tx).is_ok());
+            }
+
+            let mut merged = Engine::default();
+            for shard in engine.split(2, |client| client as usize % 2) {
+                merged.merge(shard);
+            }
+            assert_eq!(tx_ids(merged.history(1)), [1, 3]);
+            assert_eq!(tx_ids(merged.history(2)), [2]);
+
+            let mut bytes = Vec::new();
+            merged.snapshot(&mut bytes).unwrap();
+            let restored = Engine::restore(bytes.as_slice()).unwrap();
+            assert_eq!(tx_ids(restored.history(1)), [1, 3]);
+        }
+    }
 }
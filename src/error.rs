@@ -1,10 +1,52 @@
+use crate::currency::Currency;
+use crate::ledger::LedgerAccount;
+use crate::Origin;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io;
 use thiserror::Error;
 
+/// Broad kind of an `EngineError`, so alerting can tell business rejections,
+/// possible fraud signals among them, from bad input and from the engine
+/// itself failing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorCategory {
+    /// The transaction is well formed but the state of the account or of the
+    /// transaction it references does not allow it.
+    Business,
+    /// The transaction is wrong in itself and would be rejected whatever the
+    /// state of the engine.
+    Input,
+    /// The engine could not process the transaction.
+    System,
+}
+
+impl ErrorCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::Business => "business",
+            ErrorCategory::Input => "input",
+            ErrorCategory::System => "system",
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum EngineError {
     #[error("Account locked: {0}")]
     AccountLocked(u16),
 
+    #[error("Account closed: {0}")]
+    AccountClosed(u16),
+
+    #[error("Account not locked: {0}")]
+    AccountNotLocked(u16),
+
+    #[error("Account {0} still holds funds and cannot be closed")]
+    NonZeroBalance(u16),
+
     #[error("Invalid transaction_id {0} is a duplicate")]
     DuplicateTransaction(u32),
 
@@ -20,9 +62,240 @@ pub enum EngineError {
     #[error("Invalid transaction_id {0} does not exist")]
     NonExistentTransaction(u32),
 
-    #[error("Invalid transaction_id {0} has zero amount")]
-    ZeroAmount(u32),
+    #[error("Invalid transaction_id {0} is already disputed")]
+    AlreadyDisputed(u32),
+
+    #[error("Invalid transaction_id {0} is not disputed")]
+    NotDisputed(u32),
+
+    #[error("Invalid transaction_id {0} dispute is already closed")]
+    DisputeClosed(u32),
+
+    #[error("Invalid transaction_id {0} cannot be disputed, its amount is no longer available")]
+    InsufficientAvailable(u32),
+
+    #[error("Invalid transaction_id {0} can no longer be disputed, its dispute window has passed")]
+    DisputeWindowExpired(u32),
+
+    #[error("Insufficient funds for client {0}")]
+    InsufficientFunds(u16),
+
+    #[error("Total balance of client {0} would be negative")]
+    NegativeBalance(u16),
+
+    #[error("Missing amount for transaction_id {0}")]
+    MissingAmount(u32),
+
+    #[error("Missing destination client for transfer {0}")]
+    MissingDestination(u32),
+
+    #[error("Transfer {0} has the same source and destination")]
+    SelfTransfer(u32),
+
+    #[error("Invalid transaction_id {0} amount has more than {1} decimal places")]
+    PrecisionExceeded(u32, u32),
+
+    #[error("Invalid dispute of transaction_id {0}, a timestamp is required on both the dispute and the transaction")]
+    MissingTimestamp(u32),
+
+    #[error("Invalid transaction_id {0} amount must be positive")]
+    NonPositiveAmount(u32),
+
+    #[error(
+        "Invalid transaction_id {0} references another transaction and cannot carry an amount"
+    )]
+    UnexpectedAmount(u32),
+
+    #[error("Failed to write transaction_id {0} to the write-ahead log: {1}")]
+    WalWrite(u32, io::Error),
+
+    #[error("Failed to write admin action on client {0} to the write-ahead log: {1}")]
+    WalAdminWrite(u16, io::Error),
+}
+
+/// Why a row of input could not be read as a transaction.
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error("Expected {expected} fields but found {found}")]
+    FieldCount { expected: usize, found: usize },
+
+    #[error("Failed to read input: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A row of input that could not be read, with where it was found so it can
+/// be fixed at the source.
+#[derive(Debug, Error)]
+#[error(
+    "Invalid record on line {} of {} at byte {}: {error}",
+    .origin.row,
+    .origin.source,
+    .origin.offset
+)]
+pub struct IngestError {
+    pub origin: Origin,
+    #[source]
+    pub error: RecordError,
+}
+
+/// A balance that differs from what the ledger entries behind it sum to.
+#[derive(Debug, Error)]
+#[error("Ledger has {ledger} on {account} in {currency} but the balance is {balance}")]
+pub struct LedgerError {
+    pub account: LedgerAccount,
+    pub currency: Currency,
+    pub ledger: Decimal,
+    pub balance: Decimal,
+}
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Snapshot I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Snapshot is empty, expected a header")]
+    MissingHeader,
+
+    #[error("Invalid snapshot record on line {line}: {source}")]
+    InvalidRecord {
+        line: usize,
+        source: serde_json::Error,
+    },
+
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Snapshot ledger does not match its accounts: {0}")]
+    Ledger(#[from] LedgerError),
+}
+
+#[derive(Debug, Error)]
+pub enum WalError {
+    #[error("Write-ahead log I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid write-ahead log record on line {line}: {source}")]
+    InvalidRecord {
+        line: usize,
+        source: serde_json::Error,
+    },
+
+    #[error("Write-ahead log record on line {line} was rejected on replay: {source}")]
+    Replay { line: usize, source: EngineError },
+
+    #[error(
+        "Write-ahead log resumes after row {row} of '{input}', which is not one of the inputs"
+    )]
+    UnknownInput { input: String, row: u64 },
+
+    #[error(
+        "Write-ahead log starts at record {start} but the engine holds only {position}, restore the snapshot it follows"
+    )]
+    StartsAhead { start: u64, position: u64 },
+
+    #[error("Write-ahead log resumes in '{0}', which is given more than once")]
+    DuplicateInput(String),
+
+    #[error("Write-ahead log resumes after row {row} of '{input}', which has changed since")]
+    InputChanged { input: String, row: u64 },
+}
+
+impl EngineError {
+    /// Stable machine readable identifier for the error, suitable for reports
+    /// and alerting. Unlike the `Display` message this never changes wording.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::AccountLocked(_) => "account_locked",
+            EngineError::AccountClosed(_) => "account_closed",
+            EngineError::AccountNotLocked(_) => "account_not_locked",
+            EngineError::NonZeroBalance(_) => "non_zero_balance",
+            EngineError::DuplicateTransaction(_) => "duplicate_transaction",
+            EngineError::InvalidClient(_, _) => "invalid_client",
+            EngineError::InvalidOperationOnWithdrawal => "withdrawal_not_disputable",
+            EngineError::NonExistentClient(_) => "unknown_client",
+            EngineError::NonExistentTransaction(_) => "unknown_transaction",
+            EngineError::AlreadyDisputed(_) => "already_disputed",
+            EngineError::NotDisputed(_) => "not_disputed",
+            EngineError::DisputeClosed(_) => "dispute_closed",
+            EngineError::InsufficientAvailable(_) => "insufficient_available",
+            EngineError::DisputeWindowExpired(_) => "dispute_window_expired",
+            EngineError::InsufficientFunds(_) => "insufficient_funds",
+            EngineError::NegativeBalance(_) => "negative_balance",
+            EngineError::MissingAmount(_) => "missing_amount",
+            EngineError::MissingDestination(_) => "missing_destination",
+            EngineError::SelfTransfer(_) => "self_transfer",
+            EngineError::PrecisionExceeded(_, _) => "precision_exceeded",
+            EngineError::NonPositiveAmount(_) => "non_positive_amount",
+            EngineError::UnexpectedAmount(_) => "unexpected_amount",
+            EngineError::MissingTimestamp(_) => "missing_timestamp",
+            EngineError::WalWrite(_, _) | EngineError::WalAdminWrite(_, _) => "wal_write_failed",
+        }
+    }
+
+    /// Stable numeric counterpart of `code`, for systems that want a number.
+    /// The hundreds digit follows the category: 1 for business rejections, 2
+    /// for input errors and 3 for system failures. Numbers are never reused.
+    pub fn number(&self) -> u16 {
+        match self {
+            EngineError::AccountLocked(_) => 100,
+            EngineError::AccountClosed(_) => 101,
+            EngineError::AccountNotLocked(_) => 102,
+            EngineError::NonZeroBalance(_) => 103,
+            EngineError::InvalidClient(_, _) => 104,
+            EngineError::InvalidOperationOnWithdrawal => 105,
+            EngineError::AlreadyDisputed(_) => 106,
+            EngineError::NotDisputed(_) => 107,
+            EngineError::DisputeClosed(_) => 108,
+            EngineError::InsufficientAvailable(_) => 109,
+            EngineError::InsufficientFunds(_) => 110,
+            EngineError::NegativeBalance(_) => 111,
+            EngineError::DisputeWindowExpired(_) => 112,
+            EngineError::DuplicateTransaction(_) => 200,
+            EngineError::NonExistentClient(_) => 201,
+            EngineError::NonExistentTransaction(_) => 202,
+            EngineError::MissingAmount(_) => 203,
+            EngineError::MissingDestination(_) => 204,
+            EngineError::SelfTransfer(_) => 205,
+            EngineError::PrecisionExceeded(_, _) => 206,
+            EngineError::NonPositiveAmount(_) => 207,
+            EngineError::UnexpectedAmount(_) => 208,
+            EngineError::MissingTimestamp(_) => 209,
+            EngineError::WalWrite(_, _) | EngineError::WalAdminWrite(_, _) => 300,
+        }
+    }
 
-    #[error("Invalid transaction: {message}")]
-    InvalidTransaction { message: String },
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            EngineError::AccountLocked(_)
+            | EngineError::AccountClosed(_)
+            | EngineError::AccountNotLocked(_)
+            | EngineError::NonZeroBalance(_)
+            | EngineError::InvalidClient(_, _)
+            | EngineError::InvalidOperationOnWithdrawal
+            | EngineError::AlreadyDisputed(_)
+            | EngineError::NotDisputed(_)
+            | EngineError::DisputeClosed(_)
+            | EngineError::InsufficientAvailable(_)
+            | EngineError::InsufficientFunds(_)
+            | EngineError::NegativeBalance(_)
+            | EngineError::DisputeWindowExpired(_) => ErrorCategory::Business,
+            EngineError::DuplicateTransaction(_)
+            | EngineError::NonExistentClient(_)
+            | EngineError::NonExistentTransaction(_)
+            | EngineError::MissingAmount(_)
+            | EngineError::MissingDestination(_)
+            | EngineError::SelfTransfer(_)
+            | EngineError::PrecisionExceeded(_, _)
+            | EngineError::NonPositiveAmount(_)
+            | EngineError::UnexpectedAmount(_)
+            | EngineError::MissingTimestamp(_) => ErrorCategory::Input,
+            EngineError::WalWrite(_, _) | EngineError::WalAdminWrite(_, _) => ErrorCategory::System,
+        }
+    }
 }
@@ -0,0 +1,179 @@
+use crate::account::Account;
+use crate::admin::AdminEvent;
+use crate::engine::Engine;
+use crate::error::EngineError;
+use crate::transaction::{StoredTransaction, Transaction};
+
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Debug, Error)]
+#[error("Engine is no longer running")]
+pub struct EngineStopped;
+
+enum Command {
+    Apply {
+        tx: Transaction,
+        reply: oneshot::Sender<Result<(), EngineError>>,
+    },
+    Admin {
+        event: AdminEvent,
+        reply: oneshot::Sender<Result<(), EngineError>>,
+    },
+    Account {
+        client: u16,
+        reply: oneshot::Sender<Option<Account>>,
+    },
+    Accounts {
+        reply: oneshot::Sender<Vec<Account>>,
+    },
+    Transaction {
+        tx_id: u32,
+        reply: oneshot::Sender<Option<StoredTransaction>>,
+    },
+    History {
+        client: u16,
+        open_disputes: bool,
+        reply: oneshot::Sender<Option<Vec<StoredTransaction>>>,
+    },
+    Shutdown {
+        reply: oneshot::Sender<Engine>,
+    },
+}
+
+/// A cloneable handle to an `Engine` running on its own task.
+///
+/// The engine itself is never shared, every handle sends commands over the same
+/// channel and the task applies them one at a time, so any number of
+/// connections can feed and query a single engine.
+#[derive(Clone)]
+pub struct EngineHandle {
+    commands: mpsc::Sender<Command>,
+}
+
+impl EngineHandle {
+    pub fn spawn(engine: Engine, channel_size: usize) -> Self {
+        let (commands, rx) = mpsc::channel(channel_size);
+        tokio::spawn(run(engine, rx));
+
+        Self { commands }
+    }
+
+    pub async fn apply(&self, tx: Transaction) -> Result<Result<(), EngineError>, EngineStopped> {
+        self.request(|reply| Command::Apply { tx, reply }).await
+    }
+
+    pub async fn admin(&self, event: AdminEvent) -> Result<Result<(), EngineError>, EngineStopped> {
+        self.request(|reply| Command::Admin { event, reply }).await
+    }
+
+    pub async fn account(&self, client: u16) -> Result<Option<Account>, EngineStopped> {
+        self.request(|reply| Command::Account { client, reply })
+            .await
+    }
+
+    /// Every account, ordered by client id.
+    pub async fn accounts(&self) -> Result<Vec<Account>, EngineStopped> {
+        self.request(|reply| Command::Accounts { reply }).await
+    }
+
+    pub async fn transaction(
+        &self,
+        tx_id: u32,
+    ) -> Result<Option<StoredTransaction>, EngineStopped> {
+        self.request(|reply| Command::Transaction { tx_id, reply })
+            .await
+    }
+
+    /// The history of `client`, see `Engine::history`, or `None` if there is
+    /// no such client.
+    pub async fn history(
+        &self,
+        client: u16,
+    ) -> Result<Option<Vec<StoredTransaction>>, EngineStopped> {
+        self.request(|reply| Command::History {
+            client,
+            open_disputes: false,
+            reply,
+        })
+        .await
+    }
+
+    /// The open disputes of `client`, see `Engine::open_disputes`, or `None`
+    /// if there is no such client.
+    pub async fn open_disputes(
+        &self,
+        client: u16,
+    ) -> Result<Option<Vec<StoredTransaction>>, EngineStopped> {
+        self.request(|reply| Command::History {
+            client,
+            open_disputes: true,
+            reply,
+        })
+        .await
+    }
+
+    /// Stops the engine task and hands back the engine. Commands sent through
+    /// any other handle afterwards fail with `EngineStopped`.
+    pub async fn shutdown(self) -> Result<Engine, EngineStopped> {
+        self.request(|reply| Command::Shutdown { reply }).await
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> Result<T, EngineStopped> {
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .send(command(reply))
+            .await
+            .map_err(|_| EngineStopped)?;
+
+        response.await.map_err(|_| EngineStopped)
+    }
+}
+
+async fn run(mut engine: Engine, mut rx: mpsc::Receiver<Command>) {
+    while let Some(command) = rx.recv().await {
+        // A requester that went away no longer cares about the reply
+        match command {
+            Command::Apply { tx, reply } => {
+                let _ = reply.send(engine.apply_transaction(tx));
+            }
+            Command::Admin { event, reply } => {
+                let _ = reply.send(engine.apply_admin(event));
+            }
+            Command::Account { client, reply } => {
+                let _ = reply.send(engine.account(client).cloned());
+            }
+            Command::Accounts { reply } => {
+                let mut accounts: Vec<Account> = engine.accounts().cloned().collect();
+                accounts.sort_unstable_by_key(|account| account.client);
+                let _ = reply.send(accounts);
+            }
+            Command::Transaction { tx_id, reply } => {
+                let _ = reply.send(engine.transaction(tx_id).cloned());
+            }
+            Command::History {
+                client,
+                open_disputes,
+                reply,
+            } => {
+                let history = engine.account(client).map(|_| {
+                    let history: Vec<StoredTransaction> = if open_disputes {
+                        engine.open_disputes(client).cloned().collect()
+                    } else {
+                        engine.history(client).cloned().collect()
+                    };
+                    history
+                });
+                let _ = reply.send(history);
+            }
+            Command::Shutdown { reply } => {
+                let _ = reply.send(engine);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! HTTP/JSON front end for a shared engine.
+//!
+//! - `POST /transactions` takes a single transaction object, e.g.
+//!   `{"type":"deposit","client":1,"tx":1,"amount":"100.0"}`, or an array of
+//!   them applied in order
+//! - `GET /accounts/{client}` returns a single account, one record per currency
+//! - `GET /accounts` returns every account, ordered by client id and currency
+//! - `GET /accounts/{client}/transactions` returns the deposits, withdrawals and
+//!   transfers of a client, oldest first, with their dispute state
+//! - `GET /accounts/{client}/disputes` returns those of them still disputed
+//! - `GET /transactions/{tx}` returns a stored transaction and its dispute state
+//! - `POST /admin` takes an admin action, e.g.
+//!   `{"client":1,"action":"unlock","reason":"..."}`, and returns the account
+//!   it was applied to. It must carry an `Authorization: Bearer <token>` header
+//!   with one of the `AdminTokens`, whose operator the action is recorded
+//!   under, and is answered with `401 Unauthorized` otherwise
+//!
+//! Failures are answered with `{"status":"error","code":"...","message":"..."}`,
+//! using the same codes as the rejection report, and a status derived from the
+//! error by `status_for`. A batch is always answered with `200 OK` and one
+//! result per submitted transaction, in the order they were submitted.
+
+use crate::admin::{AdminAction, AdminEvent};
+use crate::error::EngineError;
+use crate::handle::{EngineHandle, EngineStopped};
+use crate::output::{AccountRecord, TransactionRecord};
+use crate::policy::IngestPolicy;
+use crate::rejection::MALFORMED_RECORD;
+use crate::server::ENGINE_STOPPED;
+use crate::transaction::CsvTransaction;
+
+use axum::body::Bytes;
+use axum::extract::{FromRef, Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Code of a request to an admin route without a known token.
+pub const UNAUTHORIZED: &str = "unauthorized";
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum Outcome {
+    Ok { tx: u32 },
+    Error { code: &'static str, message: String },
+}
+
+/// What every handler has access to, each taking the part it needs.
+#[derive(Clone)]
+struct ApiState {
+    handle: EngineHandle,
+    policy: IngestPolicy,
+    admin: Arc<AdminTokens>,
+}
+
+/// The operators allowed to take admin actions and the bearer token each one
+/// authenticates with. Read from lines of `operator:token`, blank lines and
+/// lines starting with `#` aside. Without any token no admin action is taken.
+#[derive(Clone, Debug, Default)]
+pub struct AdminTokens {
+    operators: Vec<(String, String)>,
+}
+
+impl AdminTokens {
+    /// The operator `token` belongs to, if any. Every token is compared in
+    /// full so the time taken does not tell how close a guess was.
+    pub fn operator(&self, token: &str) -> Option<&str> {
+        self.operators
+            .iter()
+            .fold(None, |found, (operator, known)| {
+                if constant_time_eq(token.as_bytes(), known.as_bytes()) {
+                    Some(operator.as_str())
+                } else {
+                    found
+                }
+            })
+    }
+}
+
+impl FromStr for AdminTokens {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut operators: Vec<(String, String)> = Vec::new();
+
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.split_once(':') {
+                Some((operator, token)) if !operator.is_empty() && !token.is_empty() => {
+                    if operators.iter().any(|(_, known)| known == token) {
+                        return Err(format!("Admin token on line {} is reused", index + 1));
+                    }
+                    operators.push((operator.to_string(), token.to_string()));
+                }
+                _ => {
+                    return Err(format!(
+                        "Admin token on line {} is not 'operator:token'",
+                        index + 1
+                    ))
+                }
+            }
+        }
+
+        Ok(Self { operators })
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl FromRef<ApiState> for EngineHandle {
+    fn from_ref(state: &ApiState) -> Self {
+        state.handle.clone()
+    }
+}
+
+impl FromRef<ApiState> for IngestPolicy {
+    fn from_ref(state: &ApiState) -> Self {
+        state.policy
+    }
+}
+
+impl FromRef<ApiState> for Arc<AdminTokens> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.admin.clone()
+    }
+}
+
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn into_outcome(self) -> Outcome {
+        Outcome::Error {
+            code: self.code,
+            message: self.message,
+        }
+    }
+}
+
+impl From<EngineError> for ApiError {
+    fn from(e: EngineError) -> Self {
+        ApiError::new(status_for(&e), e.code(), e)
+    }
+}
+
+impl From<EngineStopped> for ApiError {
+    fn from(e: EngineStopped) -> Self {
+        ApiError::new(StatusCode::SERVICE_UNAVAILABLE, ENGINE_STOPPED, e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status == StatusCode::UNAUTHORIZED {
+            let challenge = [(header::WWW_AUTHENTICATE, "Bearer")];
+            return (self.status, challenge, Json(self.into_outcome())).into_response();
+        }
+        (self.status, Json(self.into_outcome())).into_response()
+    }
+}
+
+/// The status a rejected request is answered with.
+pub fn status_for(e: &EngineError) -> StatusCode {
+    match e {
+        EngineError::NonExistentClient(_) | EngineError::NonExistentTransaction(_) => {
+            StatusCode::NOT_FOUND
+        }
+        EngineError::AccountLocked(_)
+        | EngineError::AccountClosed(_)
+        | EngineError::AccountNotLocked(_)
+        | EngineError::NonZeroBalance(_)
+        | EngineError::DuplicateTransaction(_)
+        | EngineError::AlreadyDisputed(_)
+        | EngineError::NotDisputed(_)
+        | EngineError::DisputeClosed(_)
+        | EngineError::InsufficientAvailable(_) => StatusCode::CONFLICT,
+        EngineError::InvalidClient(_, _)
+        | EngineError::InvalidOperationOnWithdrawal
+        | EngineError::InsufficientFunds(_)
+        | EngineError::NegativeBalance(_)
+        | EngineError::DisputeWindowExpired(_)
+        | EngineError::MissingAmount(_)
+        | EngineError::MissingDestination(_)
+        | EngineError::SelfTransfer(_)
+        | EngineError::PrecisionExceeded(_, _)
+        | EngineError::NonPositiveAmount(_)
+        | EngineError::UnexpectedAmount(_)
+        | EngineError::MissingTimestamp(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        EngineError::WalWrite(_, _) | EngineError::WalAdminWrite(_, _) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Builds the API, transactions being read under `policy` and admin actions
+/// taken by the operators in `admin`.
+pub fn router(handle: EngineHandle, policy: IngestPolicy, admin: AdminTokens) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transactions))
+        .route("/transactions/{tx}", get(get_transaction))
+        .route("/accounts", get(get_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .route("/accounts/{client}/transactions", get(get_history))
+        .route("/accounts/{client}/disputes", get(get_open_disputes))
+        .route("/admin", post(submit_admin))
+        .with_state(ApiState {
+            handle,
+            policy,
+            admin: Arc::new(admin),
+        })
+}
+
+/// Serves the API on `listener` until the returned future is dropped.
+pub async fn serve(
+    listener: TcpListener,
+    handle: EngineHandle,
+    policy: IngestPolicy,
+    admin: AdminTokens,
+) -> io::Result<()> {
+    axum::serve(listener, router(handle, policy, admin)).await
+}
+
+async fn submit_transactions(
+    State(handle): State<EngineHandle>,
+    State(policy): State<IngestPolicy>,
+    body: Bytes,
+) -> Response {
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            return ApiError::new(StatusCode::BAD_REQUEST, MALFORMED_RECORD, e).into_response()
+        }
+    };
+
+    match body {
+        Value::Array(items) => {
+            let mut outcomes = Vec::with_capacity(items.len());
+            for item in items {
+                let outcome = match apply(&handle, item, &policy).await {
+                    Ok(tx) => Outcome::Ok { tx },
+                    Err(e) => e.into_outcome(),
+                };
+                outcomes.push(outcome);
+            }
+
+            Json(outcomes).into_response()
+        }
+        item => match apply(&handle, item, &policy).await {
+            Ok(tx) => Json(Outcome::Ok { tx }).into_response(),
+            Err(e) => e.into_response(),
+        },
+    }
+}
+
+async fn apply(handle: &EngineHandle, item: Value, policy: &IngestPolicy) -> Result<u32, ApiError> {
+    let csv_tx: CsvTransaction = serde_json::from_value(item)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, MALFORMED_RECORD, e))?;
+    let tx_id = csv_tx.tx;
+
+    handle.apply(csv_tx.into_transaction(policy)?).await??;
+
+    Ok(tx_id)
+}
+
+/// An admin action as submitted, the operator being the one its token
+/// belongs to rather than anything the body claims.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AdminRequest {
+    client: u16,
+    action: AdminAction,
+    reason: String,
+}
+
+async fn submit_admin(
+    State(handle): State<EngineHandle>,
+    State(admin): State<Arc<AdminTokens>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Vec<AccountRecord>>, ApiError> {
+    let operator = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| admin.operator(token.trim()))
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                UNAUTHORIZED,
+                "Admin actions need a known bearer token",
+            )
+        })?;
+
+    let request: AdminRequest = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, MALFORMED_RECORD, e))?;
+    let client = request.client;
+
+    let event = AdminEvent::new(client, request.action, operator, request.reason);
+    handle.admin(event).await??;
+
+    get_account(State(handle), Path(client)).await
+}
+
+async fn get_account(
+    State(handle): State<EngineHandle>,
+    Path(client): Path<u16>,
+) -> Result<Json<Vec<AccountRecord>>, ApiError> {
+    match handle.account(client).await? {
+        Some(account) => Ok(Json(AccountRecord::rows(&account))),
+        None => Err(EngineError::NonExistentClient(client).into()),
+    }
+}
+
+async fn get_accounts(
+    State(handle): State<EngineHandle>,
+) -> Result<Json<Vec<AccountRecord>>, ApiError> {
+    let accounts = handle.accounts().await?;
+
+    Ok(Json(
+        accounts.iter().flat_map(AccountRecord::rows).collect(),
+    ))
+}
+
+async fn get_history(
+    State(handle): State<EngineHandle>,
+    Path(client): Path<u16>,
+) -> Result<Json<Vec<TransactionRecord>>, ApiError> {
+    match handle.history(client).await? {
+        Some(history) => Ok(Json(history.iter().map(TransactionRecord::from).collect())),
+        None => Err(EngineError::NonExistentClient(client).into()),
+    }
+}
+
+async fn get_open_disputes(
+    State(handle): State<EngineHandle>,
+    Path(client): Path<u16>,
+) -> Result<Json<Vec<TransactionRecord>>, ApiError> {
+    match handle.open_disputes(client).await? {
+        Some(disputes) => Ok(Json(disputes.iter().map(TransactionRecord::from).collect())),
+        None => Err(EngineError::NonExistentClient(client).into()),
+    }
+}
+
+async fn get_transaction(
+    State(handle): State<EngineHandle>,
+    Path(tx_id): Path<u32>,
+) -> Result<Json<TransactionRecord>, ApiError> {
+    match handle.transaction(tx_id).await? {
+        Some(stored) => Ok(Json(TransactionRecord::from(&stored))),
+        None => Err(EngineError::NonExistentTransaction(tx_id).into()),
+    }
+}
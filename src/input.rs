@@ -0,0 +1,407 @@
+//! Opening transaction inputs and reading records out of them.
+//!
+//! Inputs are decompressed on the fly when needed and read as one of three
+//! formats, all producing the same `CsvTransaction` rows:
+//!
+//! - CSV with a `type,client,tx,amount` header
+//! - NDJSON, one `{"type":"deposit","client":1,"tx":1,"amount":"1.0"}` per line
+//! - a JSON array of such objects, read one element at a time
+//!
+//! Every format takes an optional `currency`, e.g. a CSV header of
+//! `type,client,tx,amount,currency`, defaulting to USD when absent or empty,
+//! and a `to` client credited by `transfer` rows.
+
+use crate::error::RecordError;
+use crate::{Origin, Record};
+
+use csv::ReaderBuilder;
+use flate2::read::MultiGzDecoder;
+use serde::de::Error as _;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Input path that reads from stdin instead of a file.
+pub const STDIN_PATH: &str = "-";
+
+/// Extensions of compressed inputs, ignored when picking the format.
+const COMPRESSED_EXTENSIONS: [&str; 2] = ["gz", "zst"];
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression from the first bytes of an input.
+    pub fn sniff(magic: &[u8]) -> Self {
+        if magic.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    Ndjson,
+    JsonArray,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(InputFormat::Csv),
+            "ndjson" | "jsonl" => Ok(InputFormat::Ndjson),
+            "json" => Ok(InputFormat::JsonArray),
+            _ => Err(format!("Unknown input format '{}'", s)),
+        }
+    }
+}
+
+impl InputFormat {
+    /// Picks the format from the file extension, looking through a trailing
+    /// `.gz` or `.zst`: `.csv`, `.ndjson` or `.jsonl` and `.json`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let mut path = Path::new(path);
+        let mut ext = extension(path)?;
+
+        if COMPRESSED_EXTENSIONS.contains(&ext.as_str()) {
+            path = Path::new(path.file_stem()?);
+            ext = extension(path)?;
+        }
+
+        ext.parse().ok()
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+}
+
+/// Whether `path` names an input we know how to read, going by its extension.
+pub fn has_input_extension(path: &str) -> bool {
+    InputFormat::from_path(path).is_some()
+}
+
+/// Opens the file at `path`, or stdin if `path` is `-`.
+pub fn open_input(path: &str) -> io::Result<Box<dyn Read>> {
+    if path == STDIN_PATH {
+        decompress(io::stdin().lock())
+    } else {
+        decompress(File::open(path)?)
+    }
+}
+
+/// Wraps `reader` in a streaming decoder if it starts with a gzip or zstd
+/// header. The compression is sniffed rather than taken from the file name so
+/// piped input is handled the same way as files.
+pub fn decompress<'a, R: Read + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    // A pipe may hand over fewer bytes than asked for, so keep reading until the
+    // longest magic number is in or the input ends
+    let mut magic = [0; ZSTD_MAGIC.len()];
+    let mut read = 0;
+    while read < magic.len() {
+        match reader.read(&mut magic[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    let compression = Compression::sniff(&magic[..read]);
+    let reader = Cursor::new(magic).take(read as u64).chain(reader);
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+    })
+}
+
+/// Reads the records of `reader` as `format`. Only CSV can fail up front, when
+/// the header cannot be read.
+pub fn read_records<'a, R: Read + 'a>(
+    reader: R,
+    source: &str,
+    format: InputFormat,
+) -> Result<Box<dyn Iterator<Item = Record> + 'a>, RecordError> {
+    let source = source.to_string();
+
+    Ok(match format {
+        InputFormat::Csv => Box::new(csv_records(reader, source)?),
+        InputFormat::Ndjson => Box::new(ndjson_records(reader, source)),
+        InputFormat::JsonArray => Box::new(JsonArrayRecords::new(reader, source)),
+    })
+}
+
+fn csv_records<'a, R: Read + 'a>(
+    reader: R,
+    source: String,
+) -> Result<impl Iterator<Item = Record> + 'a, RecordError> {
+    // Rows of the wrong length are let through and checked here, so they are
+    // reported with their content rather than as a bare position
+    let mut rdr = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = rdr.headers()?.clone();
+
+    Ok(rdr.into_records().map(move |result| match result {
+        Ok(record) => Record {
+            origin: Origin {
+                source: source.clone(),
+                row: record.position().map_or(0, |pos| pos.line()),
+                offset: record.position().map_or(0, |pos| pos.byte()),
+                raw: record.iter().collect::<Vec<_>>().join(","),
+            },
+            result: if record.len() != headers.len() {
+                Err(RecordError::FieldCount {
+                    expected: headers.len(),
+                    found: record.len(),
+                })
+            } else {
+                record
+                    .deserialize(Some(&headers))
+                    .map_err(RecordError::from)
+            },
+        },
+        Err(e) => Record {
+            origin: Origin {
+                source: source.clone(),
+                row: e.position().map_or(0, |pos| pos.line()),
+                offset: e.position().map_or(0, |pos| pos.byte()),
+                raw: String::new(),
+            },
+            result: Err(e.into()),
+        },
+    }))
+}
+
+fn ndjson_records<'a, R: Read + 'a>(
+    reader: R,
+    source: String,
+) -> impl Iterator<Item = Record> + 'a {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut row = 0;
+    let mut offset = 0;
+    let mut failed = false;
+
+    std::iter::from_fn(move || loop {
+        // A failed read is reported once, retrying it would only fail again
+        if failed {
+            return None;
+        }
+        row += 1;
+
+        let start = offset;
+        let origin = |raw: &str| Origin {
+            source: source.clone(),
+            row,
+            offset: start,
+            raw: raw.to_string(),
+        };
+
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) => return None,
+            Ok(read) if line.trim().is_empty() => offset += read as u64,
+            Ok(read) => {
+                offset += read as u64;
+                let raw = line.trim();
+                return Some(Record {
+                    origin: origin(raw),
+                    result: serde_json::from_str(raw).map_err(RecordError::from),
+                });
+            }
+            Err(e) => {
+                failed = true;
+                return Some(Record {
+                    origin: origin(""),
+                    result: Err(e.into()),
+                });
+            }
+        }
+    })
+}
+
+/// Counts the bytes read through it, so records can be given an offset.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+/// Walks a top-level JSON array element by element, so the array never has to
+/// fit in memory.
+struct JsonArrayRecords<R: Read> {
+    reader: BufReader<CountingReader<R>>,
+    source: String,
+    index: u64,
+    state: ArrayState,
+}
+
+#[derive(PartialEq)]
+enum ArrayState {
+    Start,
+    Elements,
+    Done,
+}
+
+impl<R: Read> JsonArrayRecords<R> {
+    fn new(reader: R, source: String) -> Self {
+        Self {
+            reader: BufReader::new(CountingReader {
+                inner: reader,
+                count: 0,
+            }),
+            source,
+            index: 0,
+            state: ArrayState::Start,
+        }
+    }
+
+    /// Skips whitespace and consumes the next byte, `None` at the end of input.
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            let Some(&byte) = buf.first() else {
+                return Ok(None);
+            };
+            self.reader.consume(1);
+
+            if !byte.is_ascii_whitespace() {
+                return Ok(Some(byte));
+            }
+        }
+    }
+
+    /// Consumes the `[` or `,` in front of the next element and reports whether
+    /// there is one.
+    fn advance(&mut self) -> Result<bool, RecordError> {
+        let expected = match self.state {
+            ArrayState::Start => b'[',
+            ArrayState::Elements => b',',
+            ArrayState::Done => return Ok(false),
+        };
+
+        match self.next_byte()? {
+            Some(b']') if self.state == ArrayState::Elements => Ok(false),
+            Some(byte) if byte == expected => {
+                if self.state == ArrayState::Start && self.peek_byte()? == Some(b']') {
+                    self.next_byte()?;
+                    return Ok(false);
+                }
+                self.state = ArrayState::Elements;
+                // Stop at the element itself so its offset points at it
+                self.peek_byte()?;
+                Ok(true)
+            }
+            Some(byte) => Err(syntax_error(format!(
+                "expected '{}' but found '{}'",
+                expected as char, byte as char
+            ))),
+            None => Err(syntax_error("unexpected end of input")),
+        }
+    }
+
+    fn peek_byte(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            match buf.first() {
+                Some(byte) if byte.is_ascii_whitespace() => self.reader.consume(1),
+                byte => return Ok(byte.copied()),
+            }
+        }
+    }
+
+    /// Offset of the next byte to be read, the buffered bytes not counting.
+    fn offset(&self) -> u64 {
+        self.reader.get_ref().count - self.reader.buffer().len() as u64
+    }
+
+    fn origin(&self, offset: u64, raw: String) -> Origin {
+        Origin {
+            source: self.source.clone(),
+            row: self.index,
+            offset,
+            raw,
+        }
+    }
+}
+
+impl<R: Read> Iterator for JsonArrayRecords<R> {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        let more = self.advance();
+        if !matches!(more, Ok(true)) {
+            self.state = ArrayState::Done;
+        }
+        self.index += 1;
+
+        match more {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => {
+                return Some(Record {
+                    origin: self.origin(self.offset(), String::new()),
+                    result: Err(e),
+                })
+            }
+        }
+
+        let offset = self.offset();
+        let mut deserializer = serde_json::Deserializer::from_reader(&mut self.reader);
+        let value = match Value::deserialize(&mut deserializer) {
+            // The deserializer peeks one byte past a bare number, which would
+            // swallow the separator after it, so there is no carrying on
+            Ok(Value::Number(_)) => Err(syntax_error("expected a transaction object")),
+            result => result.map_err(RecordError::from),
+        };
+
+        match value {
+            Ok(value) => Some(Record {
+                origin: self.origin(offset, value.to_string()),
+                result: serde_json::from_value(value).map_err(RecordError::from),
+            }),
+            Err(e) => {
+                self.state = ArrayState::Done;
+                Some(Record {
+                    origin: self.origin(offset, String::new()),
+                    result: Err(e),
+                })
+            }
+        }
+    }
+}
+
+fn syntax_error(message: impl std::fmt::Display) -> RecordError {
+    RecordError::Json(serde_json::Error::custom(message))
+}
@@ -0,0 +1,249 @@
+//! Double-entry ledger behind the account balances.
+//!
+//! Every movement of funds is posted as an `Entry` debiting one ledger account
+//! and crediting another by the same amount, so the ledger is balanced by
+//! construction. There are three kinds of ledger account, each kept per
+//! currency:
+//!
+//! - `available:<client>` and `held:<client>`, what the engine owes a client
+//! - `external`, the settlement account funds come from and go to
+//!
+//! Client accounts are liabilities, credits add to them and debits take from
+//! them, while `external` is the matching asset. Summing the entries of a
+//! client account gives its `available` or `held` balance and `external` always
+//! comes to the total of every client, which is what `Ledger::verify` checks.
+
+use crate::account::Account;
+use crate::currency::Currency;
+use crate::error::LedgerError;
+use crate::transaction::TransactionType;
+
+use rust_decimal::Decimal;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    Available(u16),
+    Held(u16),
+    External,
+}
+
+impl LedgerAccount {
+    /// The client the account belongs to, `None` for `External`.
+    pub fn client(&self) -> Option<u16> {
+        match self {
+            LedgerAccount::Available(client) | LedgerAccount::Held(client) => Some(*client),
+            LedgerAccount::External => None,
+        }
+    }
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::Available(client) => write!(f, "available:{}", client),
+            LedgerAccount::Held(client) => write!(f, "held:{}", client),
+            LedgerAccount::External => f.write_str("external"),
+        }
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid ledger account '{}'", s);
+
+        match s.split_once(':') {
+            None if s == "external" => Ok(LedgerAccount::External),
+            Some((kind, client)) => {
+                let client = client.parse().map_err(|_| invalid())?;
+                match kind {
+                    "available" => Ok(LedgerAccount::Available(client)),
+                    "held" => Ok(LedgerAccount::Held(client)),
+                    _ => Err(invalid()),
+                }
+            }
+            None => Err(invalid()),
+        }
+    }
+}
+
+impl Serialize for LedgerAccount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LedgerAccount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let account = String::deserialize(deserializer)?;
+        account.parse().map_err(D::Error::custom)
+    }
+}
+
+/// A single posting: `amount` moves from `debit` to `credit` in `currency`
+/// because of the transaction `tx` of kind `kind`. For disputes, resolves and
+/// chargebacks `tx` is the id of the transaction they reference.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    pub tx: u32,
+    pub kind: TransactionType,
+    pub currency: Currency,
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: Decimal,
+}
+
+impl Entry {
+    /// A negative `amount` is posted the other way round, so every entry
+    /// moves a positive amount.
+    pub fn new(
+        tx: u32,
+        kind: TransactionType,
+        currency: Currency,
+        debit: LedgerAccount,
+        credit: LedgerAccount,
+        amount: Decimal,
+    ) -> Self {
+        let (debit, credit, amount) = if amount.is_sign_negative() {
+            (credit, debit, -amount)
+        } else {
+            (debit, credit, amount)
+        };
+
+        Self {
+            tx,
+            kind,
+            currency,
+            debit,
+            credit,
+            amount,
+        }
+    }
+
+    /// The client whose accounts the entry touches. Entries never span
+    /// clients, except transfers, whose clients share a shard.
+    pub fn client(&self) -> Option<u16> {
+        self.debit.client().or(self.credit.client())
+    }
+}
+
+/// Append-only list of entries, in the order they were posted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ledger {
+    entries: Vec<Entry>,
+}
+
+impl Ledger {
+    /// Appends `entries`, skipping those that move nothing.
+    pub fn post(&mut self, entries: impl IntoIterator<Item = Entry>) {
+        self.entries
+            .extend(entries.into_iter().filter(|entry| !entry.amount.is_zero()));
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The balance of `account` in `currency`: credits less debits for client
+    /// accounts and debits less credits for `external`.
+    pub fn balance(&self, account: LedgerAccount, currency: Currency) -> Decimal {
+        self.balances()
+            .get(&(account, currency))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The balance of every ledger account that has seen an entry.
+    pub fn balances(&self) -> BTreeMap<(LedgerAccount, Currency), Decimal> {
+        let mut balances = BTreeMap::new();
+
+        for entry in &self.entries {
+            let sign = |account: LedgerAccount| match account {
+                LedgerAccount::External => -Decimal::ONE,
+                _ => Decimal::ONE,
+            };
+
+            *balances
+                .entry((entry.debit, entry.currency))
+                .or_insert(Decimal::ZERO) -= sign(entry.debit) * entry.amount;
+            *balances
+                .entry((entry.credit, entry.currency))
+                .or_insert(Decimal::ZERO) += sign(entry.credit) * entry.amount;
+        }
+
+        balances
+    }
+
+    /// Checks the balances of `accounts` against the ledger: every available
+    /// and held balance must be what its entries sum to, and `external` the
+    /// sum of every total.
+    pub fn verify<'a>(
+        &self,
+        accounts: impl Iterator<Item = &'a Account>,
+    ) -> Result<(), LedgerError> {
+        let mut ledger = self.balances();
+        let mut external: BTreeMap<Currency, Decimal> = BTreeMap::new();
+
+        let mut check = |account: LedgerAccount, currency: Currency, balance: Decimal| {
+            let expected = ledger.remove(&(account, currency)).unwrap_or_default();
+            if expected != balance {
+                return Err(LedgerError {
+                    account,
+                    currency,
+                    ledger: expected,
+                    balance,
+                });
+            }
+            Ok(())
+        };
+
+        for account in accounts {
+            for (&currency, balance) in &account.balances {
+                check(
+                    LedgerAccount::Available(account.client),
+                    currency,
+                    balance.available,
+                )?;
+                check(LedgerAccount::Held(account.client), currency, balance.held)?;
+                *external.entry(currency).or_default() += balance.total;
+            }
+        }
+
+        for (currency, total) in external {
+            check(LedgerAccount::External, currency, total)?;
+        }
+
+        // Whatever is left has entries but no balance to match them
+        match ledger.into_iter().find(|(_, amount)| !amount.is_zero()) {
+            Some(((account, currency), amount)) => Err(LedgerError {
+                account,
+                currency,
+                ledger: amount,
+                balance: Decimal::ZERO,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes every entry as CSV, in posting order, under a
+    /// `tx,kind,currency,debit,credit,amount` header.
+    pub fn write_csv<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for entry in &self.entries {
+            writer.serialize(entry).map_err(io::Error::from)?;
+        }
+        writer.flush()
+    }
+
+    /// Appends the entries of `other`, which must not touch the same clients.
+    pub fn merge(&mut self, other: Ledger) {
+        self.entries.extend(other.entries);
+    }
+}
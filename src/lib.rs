@@ -1,26 +1,115 @@
 pub mod account;
+pub mod admin;
+pub mod currency;
 pub mod engine;
 pub mod error;
+pub mod handle;
+pub mod http;
+pub mod input;
+pub mod ledger;
+pub mod output;
+pub mod policy;
+pub mod rejection;
+pub mod server;
+pub mod sharded;
+pub mod snapshot;
+pub mod statement;
 pub mod transaction;
+pub mod wal;
 
+use crate::error::{IngestError, RecordError};
+use crate::input::InputFormat;
 use crate::transaction::CsvTransaction;
-use csv::ReaderBuilder;
-use std::fs::File;
+use std::error::Error;
+use std::io::Read;
 
+/// Where in the input a record came from, carried alongside the transaction so
+/// a rejection further down the pipeline can still point back at the row.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Origin {
+    /// The input the record was read from, `-` for stdin.
+    pub source: String,
+    /// Position of the record in the input: its line for CSV, the header being
+    /// line 1, and NDJSON, or its element number for a JSON array.
+    pub row: u64,
+    /// Byte offset of the start of the record in the input, after
+    /// decompression.
+    pub offset: u64,
+    /// The trimmed record as it appeared in the input.
+    pub raw: String,
+}
+
+/// A single row of input, whether or not it could be parsed.
+#[derive(Debug)]
+pub struct Record {
+    pub origin: Origin,
+    pub result: Result<CsvTransaction, RecordError>,
+}
+
+impl Record {
+    /// The parsed row, or the error together with where the row was found.
+    pub fn into_result(self) -> Result<CsvTransaction, IngestError> {
+        self.result.map_err(|error| IngestError {
+            origin: self.origin,
+            error,
+        })
+    }
+}
+
+/// Expands input arguments into the paths to read, in order. An argument
+/// containing glob characters is replaced by every path it matches, sorted,
+/// and must match at least one. Anything else, including `-`, is kept as is.
+pub fn expand_inputs(args: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut inputs = Vec::new();
+
+    for arg in args {
+        if !arg.contains(['*', '?', '[']) {
+            inputs.push(arg.clone());
+            continue;
+        }
+
+        let start = inputs.len();
+        for path in glob::glob(arg)? {
+            inputs.push(path?.to_string_lossy().into_owned());
+        }
+
+        if inputs.len() == start {
+            return Err(format!("No files match '{}'", arg).into());
+        }
+    }
+
+    Ok(inputs)
+}
+
+/// Streams the records of the file at `path`, or of stdin if `path` is `-`,
+/// decompressing gzip and zstd input as it goes. The format is picked from the
+/// file extension, falling back to CSV.
+pub fn stream_records(path: &str) -> Result<impl Iterator<Item = Record>, Box<dyn Error>> {
+    stream_records_as(path, InputFormat::from_path(path).unwrap_or_default())
+}
+
+/// Like `stream_records` but reads the input as `format` whatever its name.
+pub fn stream_records_as(
+    path: &str,
+    format: InputFormat,
+) -> Result<impl Iterator<Item = Record>, Box<dyn Error>> {
+    stream_records_from(input::open_input(path)?, path, format)
+}
+
+/// Streams the records read from `reader` as `format`, tagging each with
+/// `source`.
+pub fn stream_records_from<'a, R: Read + 'a>(
+    reader: R,
+    source: &str,
+    format: InputFormat,
+) -> Result<impl Iterator<Item = Record> + 'a, Box<dyn Error>> {
+    Ok(input::read_records(reader, source, format)?)
+}
+
+/// Streams the transactions of the file at `path`. A row that cannot be read
+/// is yielded as an error saying where it is, the rows after it still follow.
 pub fn stream_transactions(
     path: &str,
-) -> Result<impl Iterator<Item = CsvTransaction>, Box<dyn std::error::Error>> {
-    let file = File::open(path)?;
-    let rdr = ReaderBuilder::new().trim(csv::Trim::All).from_reader(file);
-
-    // Filter out invalid records and return only valid CsvTransactions
-    Ok(rdr
-        .into_deserialize::<CsvTransaction>()
-        .filter_map(|result| match result {
-            Ok(tx) => Some(tx),
-            Err(e) => {
-                eprintln!("Skipping invalid CSV line: {}", e);
-                None
-            }
-        }))
+) -> Result<impl Iterator<Item = Result<CsvTransaction, IngestError>>, Box<dyn Error>> {
+    Ok(stream_records(path)?.map(Record::into_result))
 }
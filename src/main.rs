@@ -1,86 +1,635 @@
-use octopi::stream_transactions;
-use octopi::{engine::Engine, transaction::Transaction};
+use octopi::engine::Engine;
+use octopi::error::IngestError;
+use octopi::handle::EngineHandle;
+use octopi::http::{self, AdminTokens};
+use octopi::input::{has_input_extension, InputFormat, STDIN_PATH};
+use octopi::output::{account_writer, OutputFormat};
+use octopi::policy::{
+    days, EnginePolicy, IngestPolicy, DEFAULT_DISPUTE_WINDOW_DAYS, DEFAULT_MAX_SCALE,
+};
+use octopi::rejection::{Rejection, RejectionFormat, RejectionWriter};
+use octopi::server;
+use octopi::sharded::ShardedEngine;
+use octopi::statement::{StatementBuilder, StatementRange};
+use octopi::wal::ResumeFilter;
+use octopi::{expand_inputs, stream_records_as};
 
 use std::env;
 use std::error::Error;
-use std::io::stdout;
+use std::fs::{self, File};
+use std::io::{stdout, BufReader, BufWriter};
 use std::path::Path;
+use tokio::net::TcpListener;
 use tokio::sync::mpsc;
 
 const DEFAULT_CHANNEL_SIZE: usize = 100;
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:7878";
+
+struct Args {
+    serve: bool,
+    /// Client to write a statement for instead of the accounts.
+    statement: Option<u16>,
+    range: StatementRange,
+    listen: String,
+    http_listen: Option<String>,
+    /// Operators allowed to take admin actions over HTTP, see `AdminTokens`.
+    admin_tokens_path: Option<String>,
+    inputs: Vec<String>,
+    input_format: Option<InputFormat>,
+    output_format: OutputFormat,
+    rejections_path: Option<String>,
+    /// Malformed rows tolerated before giving up on the run, any by default.
+    max_errors: Option<u64>,
+    restore_path: Option<String>,
+    snapshot_path: Option<String>,
+    ledger_path: Option<String>,
+    wal_path: Option<String>,
+    shards: usize,
+    policy: EnginePolicy,
+    ingest: IngestPolicy,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let csv_path = parse_args();
+    let args = parse_args();
+
+    if args.serve {
+        return serve(&args).await;
+    }
+
+    for path in &args.inputs {
+        validate_input_file(path, args.input_format.is_some());
+    }
 
-    validate_csv_file(&csv_path);
-    process_transactions(&csv_path).await
+    match args.statement {
+        Some(client) => write_statement(&args, client),
+        None => process_transactions(&args).await,
+    }
 }
 
-fn parse_args() -> String {
+fn parse_args() -> Args {
     let args: Vec<String> = env::args().collect();
+    let serve = args.get(1).map(String::as_str) == Some("serve");
+    let statement = args.get(1).map(String::as_str) == Some("statement");
+    let mut client = None;
+    let mut positions = (None, None);
+    let mut times = (None, None);
+    let mut listen = DEFAULT_LISTEN_ADDR.to_string();
+    let mut http_listen = None;
+    let mut admin_tokens_path = None;
+    let mut inputs = Vec::new();
+    let mut input_format = None;
+    let mut output_format = OutputFormat::default();
+    let mut max_errors = None;
+    let mut rejections_path = None;
+    let mut restore_path = None;
+    let mut snapshot_path = None;
+    let mut ledger_path = None;
+    let mut wal_path = None;
+    let mut shards = 1;
+    let mut policy = EnginePolicy::default();
+    let mut ingest = IngestPolicy::default();
 
-    match args.len() {
-        1 => "transactions.csv".to_string(),
-        2 => args[1].clone(),
+    let mut iter = args.iter().skip(if serve || statement { 2 } else { 1 });
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--listen" if serve => match iter.next() {
+                Some(addr) => listen = addr.clone(),
+                None => usage(&args[0]),
+            },
+            "--http" if serve => match iter.next() {
+                Some(addr) => http_listen = Some(addr.clone()),
+                None => usage(&args[0]),
+            },
+            "--admin-tokens" if serve => match iter.next() {
+                Some(path) => admin_tokens_path = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--client" if statement => match iter.next().map(|client| client.parse()) {
+                Some(Ok(id)) => client = Some(id),
+                _ => usage(&args[0]),
+            },
+            "--from" if statement => match iter.next().map(|position| position.parse()) {
+                Some(Ok(position)) => positions.0 = Some(position),
+                _ => usage(&args[0]),
+            },
+            "--to" if statement => match iter.next().map(|position| position.parse()) {
+                Some(Ok(position)) => positions.1 = Some(position),
+                _ => usage(&args[0]),
+            },
+            "--since" if statement => match iter.next().map(|timestamp| timestamp.parse()) {
+                Some(Ok(timestamp)) => times.0 = Some(timestamp),
+                _ => usage(&args[0]),
+            },
+            "--until" if statement => match iter.next().map(|timestamp| timestamp.parse()) {
+                Some(Ok(timestamp)) => times.1 = Some(timestamp),
+                _ => usage(&args[0]),
+            },
+            "--input-format" if !serve => match iter.next().map(|format| format.parse()) {
+                Some(Ok(format)) => input_format = Some(format),
+                Some(Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    usage(&args[0]);
+                }
+                None => usage(&args[0]),
+            },
+            "--format" => match iter.next().map(|format| format.parse()) {
+                Some(Ok(format)) => output_format = format,
+                Some(Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    usage(&args[0]);
+                }
+                None => usage(&args[0]),
+            },
+            "--rejections" => match iter.next() {
+                Some(path) => rejections_path = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--restore" => match iter.next() {
+                Some(path) => restore_path = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--snapshot" => match iter.next() {
+                Some(path) => snapshot_path = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--ledger" => match iter.next() {
+                Some(path) => ledger_path = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--wal" => match iter.next() {
+                Some(path) => wal_path = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--max-errors" if !serve => match iter.next().map(|n| n.parse()) {
+                Some(Ok(n)) => max_errors = Some(n),
+                _ => usage(&args[0]),
+            },
+            "--fail-fast" if !serve => max_errors = Some(0),
+            "--shards" => match iter.next().map(|n| n.parse()) {
+                Some(Ok(n)) if n > 0 => shards = n,
+                _ => usage(&args[0]),
+            },
+            "--dispute-hold" => match iter.next().map(|hold| hold.parse()) {
+                Some(Ok(hold)) => policy.dispute_hold = hold,
+                Some(Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    usage(&args[0]);
+                }
+                None => usage(&args[0]),
+            },
+            "--dispute-withdrawals" => policy.dispute_withdrawals = true,
+            "--dispute-window" => match iter.next().map(String::as_str) {
+                Some("none") => policy.dispute_window = None,
+                Some(n) => match n.parse().ok().and_then(days) {
+                    Some(window) => policy.dispute_window = Some(window),
+                    None => usage(&args[0]),
+                },
+                None => usage(&args[0]),
+            },
+            "--untimed-disputes" => match iter.next().map(|handling| handling.parse()) {
+                Some(Ok(handling)) => policy.untimed_disputes = handling,
+                Some(Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    usage(&args[0]);
+                }
+                None => usage(&args[0]),
+            },
+            "--max-scale" => match iter.next().map(|n| n.parse()) {
+                Some(Ok(n)) => ingest.max_scale = n,
+                _ => usage(&args[0]),
+            },
+            "--allow-non-positive" => ingest.reject_non_positive = false,
+            "--allow-reference-amounts" => ingest.reject_reference_amounts = false,
+            "--excess-precision" => match iter.next().map(|handling| handling.parse()) {
+                Some(Ok(handling)) => ingest.excess_precision = handling,
+                Some(Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    usage(&args[0]);
+                }
+                None => usage(&args[0]),
+            },
+            _ if !serve => inputs.push(arg.clone()),
+            _ => usage(&args[0]),
+        }
+    }
+
+    if wal_path.is_some() && shards > 1 {
+        eprintln!("Error: --wal can only be used with a single shard");
+        usage(&args[0]);
+    }
+
+    if serve && (rejections_path.is_some() || shards > 1) {
+        eprintln!("Error: --rejections and --shards only apply to processing a file");
+        usage(&args[0]);
+    }
+
+    let range = match (positions, times) {
+        ((from, to), (None, None)) => StatementRange::Positions { from, to },
+        ((None, None), (from, to)) => StatementRange::Time { from, to },
         _ => {
-            eprintln!("Usage: {} [csv_file]", args[0]);
-            eprintln!("  csv_file: Path to CSV file (default: transactions.csv)");
+            eprintln!("Error: a statement covers either --from/--to or --since/--until");
+            usage(&args[0]);
+        }
+    };
+
+    if statement {
+        if client.is_none() {
+            eprintln!("Error: statement needs a --client");
+            usage(&args[0]);
+        }
+
+        if wal_path.is_some()
+            || rejections_path.is_some()
+            || snapshot_path.is_some()
+            || ledger_path.is_some()
+            || shards > 1
+        {
+            eprintln!(
+                "Error: --wal, --rejections, --snapshot, --ledger and --shards do not apply to statements"
+            );
+            usage(&args[0]);
+        }
+    }
+
+    if inputs.is_empty() {
+        inputs.push("transactions.csv".to_string());
+    }
+
+    let inputs = match expand_inputs(&inputs) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         }
+    };
+
+    if inputs.iter().filter(|path| *path == STDIN_PATH).count() > 1 {
+        eprintln!("Error: stdin can only be read once");
+        usage(&args[0]);
+    }
+
+    Args {
+        serve,
+        statement: client,
+        range,
+        listen,
+        http_listen,
+        admin_tokens_path,
+        inputs,
+        input_format,
+        output_format,
+        rejections_path,
+        max_errors,
+        restore_path,
+        snapshot_path,
+        ledger_path,
+        wal_path,
+        shards,
+        policy,
+        ingest,
     }
 }
 
-fn validate_csv_file(path: &str) {
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--input-format <csv|json|ndjson>] [--format <csv|json|ndjson>] [--rejections <file>] [--restore <file>] \
+         [--snapshot <file>] [--ledger <file>] [--wal <file>] [--shards <n>] [--dispute-hold <partial|full|reject>] \
+         [--dispute-withdrawals] [--dispute-window <days|none>] \
+         [--untimed-disputes <allow|reject>] [--max-scale <n>] \
+         [--excess-precision <reject|bankers|truncate|half-up>] [--allow-non-positive] \
+         [--allow-reference-amounts] [--max-errors <n> | --fail-fast] [csv_file...]",
+        program
+    );
+    eprintln!(
+        "       {} serve [--listen <addr>] [--http <addr>] [--admin-tokens <file>] [--format <csv|json|ndjson>] [--restore <file>] \
+         [--snapshot <file>] [--ledger <file>] [--wal <file>] [--dispute-hold <partial|full|reject>] [--dispute-withdrawals] \
+         [--dispute-window <days|none>] [--untimed-disputes <allow|reject>] \
+         [--max-scale <n>] [--excess-precision <reject|bankers|truncate|half-up>] [--allow-non-positive] \
+         [--allow-reference-amounts]",
+        program
+    );
+    eprintln!(
+        "       {} statement --client <id> [--from <n>] [--to <n>] [--since <timestamp>] [--until <timestamp>] \
+         [--input-format <csv|json|ndjson>] [--format <csv|json>] [--restore <file>] [--dispute-hold <partial|full|reject>] [--dispute-withdrawals] \
+         [--dispute-window <days|none>] [--untimed-disputes <allow|reject>] \
+         [--max-scale <n>] [--excess-precision <reject|bankers|truncate|half-up>] [--allow-non-positive] \
+         [--allow-reference-amounts] [csv_file...]",
+        program
+    );
+    eprintln!("  csv_file: Paths or glob patterns of input files, processed in order, or - for");
+    eprintln!("            stdin (default: transactions.csv). Gzip and zstd compressed input,");
+    eprintln!("            e.g. .csv.gz and .csv.zst, is decompressed as it is read");
+    eprintln!("  --input-format: Format of every input, csv, ndjson or a json array (default:");
+    eprintln!("                  picked by extension, .csv, .ndjson or .jsonl and .json)");
+    eprintln!("  --format: Format of the account output (default: csv)");
+    eprintln!("  --rejections: Write rejected rows to <file>, as NDJSON if it ends in");
+    eprintln!("                .ndjson or .jsonl and as CSV otherwise");
+    eprintln!("  --max-errors: Abort, writing no accounts, once more than <n> rows could not");
+    eprintln!("                be read (default: no limit)");
+    eprintln!("  --fail-fast: Abort at the first row that cannot be read, --max-errors 0");
+    eprintln!("  --restore: Start from the engine state saved in a previous snapshot");
+    eprintln!("  --snapshot: Save the engine state to <file> once the input is processed");
+    eprintln!("  --ledger: Write every ledger entry behind the balances to <file> as CSV");
+    eprintln!("  --wal: Log accepted transactions to <file> before applying them, replaying");
+    eprintln!("         whatever it already holds on startup and skipping the input rows");
+    eprintln!("         it covers. Emptied once --snapshot is written");
+    eprintln!("  --shards: Number of engine tasks to spread clients over (default: 1)");
+    eprintln!("  --dispute-hold: What a dispute holds when the disputed amount is no longer");
+    eprintln!("                  available: what is left, the full amount taking available");
+    eprintln!("                  below zero, or nothing, rejecting it (default: partial)");
+    eprintln!("  --dispute-withdrawals: Allow withdrawals to be disputed");
+    eprintln!("  --dispute-window: Days a transaction can be disputed for, measured between");
+    eprintln!(
+        "                    timestamps, or none (default: {})",
+        DEFAULT_DISPUTE_WINDOW_DAYS
+    );
+    eprintln!("  --untimed-disputes: Allow or reject disputes that cannot be checked against");
+    eprintln!("                      the window, lacking a timestamp (default: allow)");
+    eprintln!(
+        "  --max-scale: Most decimal places an amount may have (default: {})",
+        DEFAULT_MAX_SCALE
+    );
+    eprintln!("  --excess-precision: Reject amounts with more places or round them, half to");
+    eprintln!("                      even, towards zero or half up (default: reject)");
+    eprintln!("  --allow-non-positive: Accept deposits, withdrawals and transfers of zero or");
+    eprintln!("                        less instead of rejecting them");
+    eprintln!("  --allow-reference-amounts: Accept disputes, resolves and chargebacks that");
+    eprintln!("                             carry an amount, ignoring it");
+    eprintln!(
+        "  --listen: Address to accept transaction streams on (default: {})",
+        DEFAULT_LISTEN_ADDR
+    );
+    eprintln!("  --http: Also serve the HTTP/JSON API on <addr>");
+    eprintln!("  --admin-tokens: Allow the operators in <file>, one 'operator:token' per line,");
+    eprintln!("                  to take admin actions over HTTP (default: nobody)");
+    eprintln!("  serve runs until interrupted, then writes the accounts like a file run");
+    eprintln!("  statement writes the balances of one client before and after the input rows");
+    eprintln!("            --from to --to, counted from 1 across every input (default: all),");
+    eprintln!("            and each transaction in between that changed them. --since and");
+    eprintln!("            --until pick the rows by timestamp instead, a row without one");
+    eprintln!("            counting as at the latest timestamp before it");
+    std::process::exit(1);
+}
+
+/// Checks `path` exists and, unless the format was given explicitly, that its
+/// extension names a format we can read.
+fn validate_input_file(path: &str, format_given: bool) {
+    if path == STDIN_PATH {
+        return;
+    }
+
     if !Path::new(path).exists() {
         eprintln!("Error: File '{}' does not exist", path);
         std::process::exit(1);
     }
 
-    if !path.to_lowercase().ends_with(".csv") {
-        eprintln!("Error: File '{}' is not a CSV file", path);
+    if !format_given && !has_input_extension(path) {
+        eprintln!(
+            "Error: File '{}' is not a CSV, NDJSON or JSON file, pass --input-format to read it anyway",
+            path
+        );
         std::process::exit(1);
     }
 }
 
-async fn process_transactions(csv_path: &str) -> Result<(), Box<dyn Error>> {
-    let txs = stream_transactions(csv_path)?;
+async fn process_transactions(args: &Args) -> Result<(), Box<dyn Error>> {
+    // Rejections are reported from both this task and the engine shards, so they
+    // are funnelled through a channel to a single writer
+    let (rejection_channel, rejection_handle) = match &args.rejections_path {
+        Some(path) => {
+            let (tx, rx) = mpsc::channel::<Rejection>(DEFAULT_CHANNEL_SIZE);
+            let writer = RejectionWriter::new(
+                BufWriter::new(File::create(path)?),
+                RejectionFormat::from_path(path),
+            );
+            (Some(tx), Some(tokio::spawn(write_rejections(rx, writer))))
+        }
+        None => (None, None),
+    };
+
+    // A rerun against the same write-ahead log carries on after the last row
+    // the log holds, rather than checking the rows before it again
+    let engine = load_engine(args)?;
+    let mut resume = ResumeFilter::new(engine.resume_point().cloned(), &args.inputs)?;
 
-    // Create a channel to send transactions to the engine
-    // NOTE: if we wanted to have multiple senders then we could clone the channel and
-    // have many threads sending to the same recevier `rx`
-    let (tx_channel, mut rx) = mpsc::channel::<Transaction>(DEFAULT_CHANNEL_SIZE);
+    // Each shard owns its own engine and channel, clients are routed to shards
+    // so the shards never need to share any account state
+    let mut engine = ShardedEngine::from_engine(
+        engine,
+        args.shards,
+        DEFAULT_CHANNEL_SIZE,
+        rejection_channel.clone(),
+    );
 
-    // Spawn engine task
-    let engine_handle = tokio::spawn(async move {
-        let mut engine = Engine::default();
+    // Process CSV records, every input feeding the same engine in turn
+    let mut malformed = 0;
+    let mut aborted = None;
+    'inputs: for path in &args.inputs {
+        if resume.skips_input(path)? {
+            continue;
+        }
+
+        let format = args
+            .input_format
+            .or_else(|| InputFormat::from_path(path))
+            .unwrap_or_default();
+
+        for record in stream_records_as(path, format)? {
+            if resume.skips(&record.origin)? {
+                continue;
+            }
+
+            let rejection = match record.result {
+                Ok(csv_tx) => {
+                    let (client, tx_id) = (csv_tx.client, csv_tx.tx);
+
+                    match csv_tx.into_transaction(&args.ingest) {
+                        Ok(parsed_tx) => {
+                            engine.send(record.origin, parsed_tx).await;
+                            continue;
+                        }
+                        Err(e) => {
+                            eprintln!("Transaction conversion error: {:?}", e);
+                            Rejection::engine(record.origin, client, tx_id, &e)
+                        }
+                    }
+                }
+                Err(error) => {
+                    let e = IngestError {
+                        origin: record.origin,
+                        error,
+                    };
+                    eprintln!("Skipping: {}", e);
+                    malformed += 1;
+
+                    if args.max_errors.is_some_and(|max| malformed > max) {
+                        aborted = Some(e.to_string());
+                    }
+
+                    Rejection::malformed(e.origin, &e.error)
+                }
+            };
+
+            if let Some(rejections) = &rejection_channel {
+                rejections.send(rejection).await.expect("Receiver dropped");
+            }
 
-        while let Some(tx) = rx.recv().await {
-            if let Err(e) = engine.apply_transaction(tx) {
-                eprintln!("Engine error: {:?}", e);
+            if aborted.is_some() {
+                break 'inputs;
             }
         }
+    }
+
+    // Wait for the shards to drain, then close the rejection channel to signal
+    // the rejection task to finish
+    let mut engine = engine.finish().await;
+    drop(rejection_channel);
+
+    if let Some(handle) = rejection_handle {
+        handle.await??;
+    }
+
+    // The accounts would be wrong, so nothing is written
+    if let Some(e) = aborted {
+        return Err(format!("Giving up after {} malformed rows. {}", malformed, e).into());
+    }
+    resume.finish()?;
+
+    save_engine(&mut engine, args)?;
+
+    Ok(())
+}
+
+/// Replays the inputs on a fresh or restored engine and writes the statement
+/// of `client` to stdout. Every record read takes a position, malformed and
+/// rejected ones included, so positions point back at the input.
+fn write_statement(args: &Args, client: u16) -> Result<(), Box<dyn Error>> {
+    let mut statement = StatementBuilder::new(load_engine(args)?, client, args.range);
 
-        engine.dump_accounts(stdout());
-    });
+    let mut position = 0;
+    'inputs: for path in &args.inputs {
+        let format = args
+            .input_format
+            .or_else(|| InputFormat::from_path(path))
+            .unwrap_or_default();
 
-    // Process CSV transactions
-    for csv_tx in txs {
-        match csv_tx.try_into() {
-            Ok(parsed_tx) => {
-                tx_channel.send(parsed_tx).await.expect("Receiver dropped");
+        for record in stream_records_as(path, format)? {
+            position += 1;
+            if statement.ends_before(position) {
+                break 'inputs;
             }
-            Err(e) => {
-                eprintln!("Transaction conversion error: {:?}", e);
+
+            let tx = record
+                .result
+                .ok()
+                .and_then(|csv_tx| csv_tx.into_transaction(&args.ingest).ok());
+            if let Some(tx) = tx {
+                statement.replay(position, tx);
             }
         }
     }
 
-    // Close the channel to signal the engine task to finish
-    drop(tx_channel);
+    statement
+        .finish()
+        .write(BufWriter::new(stdout()), args.output_format)?;
+
+    Ok(())
+}
+
+/// Serves transaction streams over TCP, and the HTTP API if asked to, until
+/// interrupted, all connections sharing a single engine.
+async fn serve(args: &Args) -> Result<(), Box<dyn Error>> {
+    let admin: AdminTokens = match &args.admin_tokens_path {
+        Some(path) => fs::read_to_string(path)?.parse()?,
+        None => AdminTokens::default(),
+    };
+    let handle = EngineHandle::spawn(load_engine(args)?, DEFAULT_CHANNEL_SIZE);
+
+    let listener = TcpListener::bind(&args.listen).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+
+    let http_listener = match &args.http_listen {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr).await?;
+            eprintln!("Serving HTTP on {}", listener.local_addr()?);
+            Some(listener)
+        }
+        None => None,
+    };
+    let http_server = async {
+        match http_listener {
+            Some(listener) => http::serve(listener, handle.clone(), args.ingest, admin).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        result = server::serve(listener, handle.clone(), args.ingest) => result?,
+        result = http_server => result?,
+        result = tokio::signal::ctrl_c() => result?,
+    }
+
+    let mut engine = handle.shutdown().await?;
+    save_engine(&mut engine, args)
+}
+
+/// Builds the starting engine from `--restore` and `--wal`, applying the
+/// dispute policy before the log is replayed.
+fn load_engine(args: &Args) -> Result<Engine, Box<dyn Error>> {
+    let mut engine = match &args.restore_path {
+        Some(path) => Engine::restore(BufReader::new(File::open(path)?))?,
+        None => Engine::default(),
+    }
+    .with_policy(args.policy);
 
-    // Wait for the engine task to complete
-    engine_handle.await?;
+    if let Some(path) = &args.wal_path {
+        engine = engine.with_wal(path)?;
+    }
+
+    Ok(engine)
+}
+
+/// Writes the `--snapshot` if requested and the accounts to stdout. Once the
+/// snapshot is in place the write-ahead log is emptied, the snapshot holding
+/// everything it did.
+fn save_engine(engine: &mut Engine, args: &Args) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = &args.snapshot_path {
+        write_snapshot(engine, path)?;
+        engine.truncate_wal()?;
+    }
+
+    if let Some(path) = &args.ledger_path {
+        engine
+            .ledger()
+            .write_csv(BufWriter::new(File::create(path)?))?;
+    }
+
+    let mut writer = account_writer(BufWriter::new(stdout()), args.output_format);
+    engine.write_accounts(writer.as_mut())?;
 
     Ok(())
 }
+
+/// Writes the snapshot next to `path` first and renames it into place, so an
+/// interrupted run never leaves a truncated snapshot behind.
+fn write_snapshot(engine: &Engine, path: &str) -> std::io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+
+    let mut file = BufWriter::new(File::create(&tmp_path)?);
+    engine.snapshot(&mut file)?;
+    file.into_inner()?.sync_all()?;
+
+    fs::rename(tmp_path, path)
+}
+
+async fn write_rejections(
+    mut rx: mpsc::Receiver<Rejection>,
+    mut writer: RejectionWriter<BufWriter<File>>,
+) -> std::io::Result<()> {
+    while let Some(rejection) = rx.recv().await {
+        writer.write(&rejection)?;
+    }
+
+    writer.flush()
+}
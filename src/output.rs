@@ -0,0 +1,237 @@
+use crate::account::{Account, Balance};
+use crate::currency::Currency;
+use crate::transaction::{StoredTransaction, TransactionState, TransactionType};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Number of decimal places balances are reported with.
+pub(crate) const OUTPUT_PRECISION: u32 = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            _ => Err(format!("Unknown output format '{}'", s)),
+        }
+    }
+}
+
+/// The externally visible view of one currency of an account, balances
+/// rounded for output.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AccountRecord {
+    pub client: u16,
+    pub currency: Currency,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl AccountRecord {
+    pub fn new(account: &Account, currency: Currency, balance: &Balance) -> Self {
+        Self {
+            client: account.client,
+            currency,
+            available: balance.available.round_dp(OUTPUT_PRECISION),
+            held: balance.held.round_dp(OUTPUT_PRECISION),
+            total: balance.total.round_dp(OUTPUT_PRECISION),
+            locked: account.locked,
+        }
+    }
+
+    /// One record per currency of `account`, ordered by currency. An account
+    /// without any balance still gets a zero record in the default currency so
+    /// it never disappears from the output.
+    pub fn rows(account: &Account) -> Vec<Self> {
+        if account.balances.is_empty() {
+            return vec![Self::new(account, Currency::default(), &Balance::default())];
+        }
+
+        account
+            .balances
+            .iter()
+            .map(|(currency, balance)| Self::new(account, *currency, balance))
+            .collect()
+    }
+}
+
+/// The externally visible view of a stored transaction, laid out like an input
+/// record plus its dispute state and what its dispute holds.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TransactionRecord {
+    #[serde(rename = "type")]
+    pub kind: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Decimal>,
+    pub currency: Currency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    pub state: TransactionState,
+    pub held: Decimal,
+}
+
+impl From<&StoredTransaction> for TransactionRecord {
+    fn from(stored: &StoredTransaction) -> Self {
+        Self {
+            kind: stored.tx.kind.clone(),
+            client: stored.tx.client,
+            tx: stored.tx.tx_id,
+            amount: stored.tx.amount,
+            currency: stored.tx.currency,
+            to: stored.tx.to,
+            timestamp: stored.tx.timestamp,
+            state: stored.state,
+            held: stored.held,
+        }
+    }
+}
+
+/// A sink for account state. Accounts are written one at a time, as one row
+/// per currency, and `finish` must be called once all of them have been
+/// written.
+pub trait AccountWriter {
+    fn write_account(&mut self, account: &Account) -> io::Result<()>;
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Returns a writer for `format` wrapping `writer`.
+pub fn account_writer<'a, W: Write + 'a>(
+    writer: W,
+    format: OutputFormat,
+) -> Box<dyn AccountWriter + 'a> {
+    match format {
+        OutputFormat::Csv => Box::new(CsvAccountWriter::new(writer)),
+        OutputFormat::Json => Box::new(JsonAccountWriter::new(writer)),
+        OutputFormat::Ndjson => Box::new(NdjsonAccountWriter::new(writer)),
+    }
+}
+
+/// `client,currency,available,held,total,locked` with a header line, even when
+/// there are no accounts.
+pub struct CsvAccountWriter<W: Write> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvAccountWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            writeln!(self.writer, "client,currency,available,held,total,locked")?;
+            self.header_written = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> AccountWriter for CsvAccountWriter<W> {
+    fn write_account(&mut self, account: &Account) -> io::Result<()> {
+        self.write_header()?;
+
+        for record in AccountRecord::rows(account) {
+            writeln!(
+                self.writer,
+                "{},{},{},{},{},{}",
+                record.client,
+                record.currency,
+                record.available,
+                record.held,
+                record.total,
+                record.locked
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+/// A single JSON array holding one object per account and currency.
+pub struct JsonAccountWriter<W: Write> {
+    writer: W,
+    written: usize,
+}
+
+impl<W: Write> JsonAccountWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, written: 0 }
+    }
+}
+
+impl<W: Write> AccountWriter for JsonAccountWriter<W> {
+    fn write_account(&mut self, account: &Account) -> io::Result<()> {
+        for record in AccountRecord::rows(account) {
+            let separator = if self.written == 0 { "[" } else { "," };
+            self.writer.write_all(separator.as_bytes())?;
+            serde_json::to_writer(&mut self.writer, &record)?;
+            self.written += 1;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.written == 0 {
+            self.writer.write_all(b"[")?;
+        }
+        writeln!(self.writer, "]")?;
+        self.writer.flush()
+    }
+}
+
+/// One JSON object per line, per account and currency.
+pub struct NdjsonAccountWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonAccountWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> AccountWriter for NdjsonAccountWriter<W> {
+    fn write_account(&mut self, account: &Account) -> io::Result<()> {
+        for record in AccountRecord::rows(account) {
+            serde_json::to_writer(&mut self.writer, &record)?;
+            writeln!(self.writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,201 @@
+use crate::error::EngineError;
+use crate::transaction::Transaction;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Decimal places amounts are accepted with unless configured otherwise, as
+/// many as balances are reported with.
+pub const DEFAULT_MAX_SCALE: u32 = 4;
+
+/// Days a transaction can be disputed for unless configured otherwise, the
+/// window card network rules give cardholders.
+pub const DEFAULT_DISPUTE_WINDOW_DAYS: u64 = 120;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What a dispute does when the client no longer has the disputed amount
+/// available, e.g. because part of a deposit has since been withdrawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DisputeHold {
+    /// Hold whatever is still available, up to the disputed amount.
+    #[default]
+    Partial,
+    /// Hold the whole disputed amount, taking `available` below zero if needed.
+    Full,
+    /// Reject the dispute.
+    Reject,
+}
+
+impl FromStr for DisputeHold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "partial" => Ok(DisputeHold::Partial),
+            "full" => Ok(DisputeHold::Full),
+            "reject" => Ok(DisputeHold::Reject),
+            _ => Err(format!("Unknown dispute hold '{}'", s)),
+        }
+    }
+}
+
+/// A dispute window of `n` days, or `None` if that many seconds do not fit in
+/// a `u64`.
+pub fn days(n: u64) -> Option<Duration> {
+    n.checked_mul(SECONDS_PER_DAY).map(Duration::from_secs)
+}
+
+/// What a dispute does when it or the transaction it references has no
+/// timestamp, so it cannot be checked against the dispute window.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UntimedDisputes {
+    /// Accept the dispute, as if it were within the window.
+    #[default]
+    Allow,
+    /// Reject the dispute.
+    Reject,
+}
+
+impl FromStr for UntimedDisputes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "allow" => Ok(UntimedDisputes::Allow),
+            "reject" => Ok(UntimedDisputes::Reject),
+            _ => Err(format!("Unknown untimed dispute handling '{}'", s)),
+        }
+    }
+}
+
+/// Rules an `Engine` applies to disputes. The default keeps the behaviour the
+/// engine always had for input without timestamps: partial holds, withdrawals
+/// that cannot be disputed and untimed disputes allowed. Timestamped disputes
+/// are held to a window of `DEFAULT_DISPUTE_WINDOW_DAYS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnginePolicy {
+    pub dispute_hold: DisputeHold,
+    /// Whether withdrawals may be disputed. A disputed withdrawal holds the
+    /// withdrawn amount on top of the balance until it is resolved, which drops
+    /// it again, or charged back, which makes it available to the client.
+    pub dispute_withdrawals: bool,
+    /// How long after a transaction it may still be disputed, measured between
+    /// the timestamps of the two. `None` never closes the window.
+    pub dispute_window: Option<Duration>,
+    pub untimed_disputes: UntimedDisputes,
+}
+
+impl Default for EnginePolicy {
+    fn default() -> Self {
+        Self {
+            dispute_hold: DisputeHold::default(),
+            dispute_withdrawals: false,
+            dispute_window: days(DEFAULT_DISPUTE_WINDOW_DAYS),
+            untimed_disputes: UntimedDisputes::default(),
+        }
+    }
+}
+
+impl EnginePolicy {
+    /// Checks `dispute` of `original` against the dispute window. A dispute
+    /// stamped before the transaction it references is within the window.
+    pub fn check_dispute_window(
+        &self,
+        original: &Transaction,
+        dispute: &Transaction,
+    ) -> Result<(), EngineError> {
+        let Some(window) = self.dispute_window else {
+            return Ok(());
+        };
+
+        match (original.timestamp, dispute.timestamp) {
+            (Some(at), Some(disputed_at)) => {
+                if disputed_at.saturating_sub(at) > window.as_secs() {
+                    return Err(EngineError::DisputeWindowExpired(dispute.tx_id));
+                }
+                Ok(())
+            }
+            _ => match self.untimed_disputes {
+                UntimedDisputes::Allow => Ok(()),
+                UntimedDisputes::Reject => Err(EngineError::MissingTimestamp(dispute.tx_id)),
+            },
+        }
+    }
+}
+
+/// What to do with an amount that has more decimal places than allowed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExcessPrecision {
+    /// Reject the transaction.
+    #[default]
+    Reject,
+    /// Round half to even.
+    Bankers,
+    /// Drop the extra places.
+    Truncate,
+    /// Round half away from zero.
+    HalfUp,
+}
+
+impl FromStr for ExcessPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(ExcessPrecision::Reject),
+            "bankers" => Ok(ExcessPrecision::Bankers),
+            "truncate" => Ok(ExcessPrecision::Truncate),
+            "half-up" => Ok(ExcessPrecision::HalfUp),
+            _ => Err(format!("Unknown excess precision handling '{}'", s)),
+        }
+    }
+}
+
+/// Rules applied to rows as they are turned into transactions, before the
+/// engine sees them. The default is strict, every check is on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IngestPolicy {
+    /// Most decimal places an amount may have, trailing zeros aside.
+    pub max_scale: u32,
+    pub excess_precision: ExcessPrecision,
+    /// Reject deposits, withdrawals and transfers of zero or less, checked
+    /// once the amount is scaled.
+    pub reject_non_positive: bool,
+    /// Reject disputes, resolves and chargebacks carrying an amount, which
+    /// would otherwise be ignored.
+    pub reject_reference_amounts: bool,
+}
+
+impl Default for IngestPolicy {
+    fn default() -> Self {
+        Self {
+            max_scale: DEFAULT_MAX_SCALE,
+            excess_precision: ExcessPrecision::default(),
+            reject_non_positive: true,
+            reject_reference_amounts: true,
+        }
+    }
+}
+
+impl IngestPolicy {
+    /// Returns `amount` brought within `max_scale`, or the error rejecting
+    /// transaction `tx_id` if it has too many places and they are not rounded.
+    pub fn scale_amount(&self, tx_id: u32, amount: Decimal) -> Result<Decimal, EngineError> {
+        if amount.normalize().scale() <= self.max_scale {
+            return Ok(amount);
+        }
+
+        let strategy = match self.excess_precision {
+            ExcessPrecision::Reject => {
+                return Err(EngineError::PrecisionExceeded(tx_id, self.max_scale))
+            }
+            ExcessPrecision::Bankers => RoundingStrategy::MidpointNearestEven,
+            ExcessPrecision::Truncate => RoundingStrategy::ToZero,
+            ExcessPrecision::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+        };
+
+        Ok(amount.round_dp_with_strategy(self.max_scale, strategy))
+    }
+}
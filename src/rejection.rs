@@ -0,0 +1,107 @@
+use crate::error::{EngineError, ErrorCategory, RecordError};
+use crate::Origin;
+
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Code reported for rows that could not be parsed into a transaction at all.
+pub const MALFORMED_RECORD: &str = "malformed_record";
+
+/// A row that was rejected, either because it could not be parsed or because
+/// the engine refused to apply it.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Rejection {
+    pub source: String,
+    pub row: u64,
+    pub raw: String,
+    pub tx: Option<u32>,
+    pub client: Option<u16>,
+    pub category: ErrorCategory,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn malformed(origin: Origin, err: &RecordError) -> Self {
+        Self {
+            source: origin.source,
+            row: origin.row,
+            raw: origin.raw,
+            tx: None,
+            client: None,
+            category: ErrorCategory::Input,
+            code: MALFORMED_RECORD,
+            message: err.to_string(),
+        }
+    }
+
+    pub fn engine(origin: Origin, client: u16, tx: u32, err: &EngineError) -> Self {
+        Self {
+            source: origin.source,
+            row: origin.row,
+            raw: origin.raw,
+            tx: Some(tx),
+            client: Some(client),
+            category: err.category(),
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RejectionFormat {
+    Csv,
+    Ndjson,
+}
+
+impl RejectionFormat {
+    /// Picks the format from the file extension, `.ndjson` and `.jsonl` select
+    /// NDJSON and anything else falls back to CSV.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .as_deref()
+        {
+            Some("ndjson") | Some("jsonl") => RejectionFormat::Ndjson,
+            _ => RejectionFormat::Csv,
+        }
+    }
+}
+
+/// Writes rejections to a sink in the chosen format, one record per row.
+pub enum RejectionWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Ndjson(W),
+}
+
+impl<W: Write> RejectionWriter<W> {
+    pub fn new(writer: W, format: RejectionFormat) -> Self {
+        match format {
+            RejectionFormat::Csv => {
+                RejectionWriter::Csv(Box::new(csv::Writer::from_writer(writer)))
+            }
+            RejectionFormat::Ndjson => RejectionWriter::Ndjson(writer),
+        }
+    }
+
+    pub fn write(&mut self, rejection: &Rejection) -> io::Result<()> {
+        match self {
+            RejectionWriter::Csv(writer) => writer.serialize(rejection).map_err(io::Error::from),
+            RejectionWriter::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, rejection)?;
+                writeln!(writer)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RejectionWriter::Csv(writer) => writer.flush(),
+            RejectionWriter::Ndjson(writer) => writer.flush(),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Line based TCP front end for a shared engine.
+//!
+//! Each connection sends one request per line and gets exactly one JSON
+//! response line back per request, in order:
+//!
+//! - a CSV transaction such as `deposit,1,1,100.0`, columns as given by the
+//!   last CSV header line sent on the connection,
+//!   `type,client,tx,amount,currency,to,timestamp` if none was sent, the
+//!   trailing `currency`, `to` and `timestamp` being optional
+//! - an NDJSON transaction such as `{"type":"deposit","client":1,"tx":1,"amount":"100.0"}`
+//! - `account <client>` for the state of a single account, one record per
+//!   currency under `accounts`
+//! - `accounts` for the state of every account
+//!
+//! Transactions are answered with `{"status":"ok","tx":1}` or
+//! `{"status":"error","code":"...","message":"..."}`, using the same codes as
+//! the rejection report. Header and blank lines get no response.
+
+use crate::error::EngineError;
+use crate::handle::{EngineHandle, EngineStopped};
+use crate::output::AccountRecord;
+use crate::policy::IngestPolicy;
+use crate::rejection::MALFORMED_RECORD;
+use crate::transaction::CsvTransaction;
+
+use csv::{ReaderBuilder, StringRecord};
+use serde::Serialize;
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const DEFAULT_HEADER: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "currency",
+    "to",
+    "timestamp",
+];
+
+/// Code reported for lines that are neither a transaction nor a query.
+pub const UNKNOWN_REQUEST: &str = "unknown_request";
+
+/// Code reported when the engine has been shut down.
+pub const ENGINE_STOPPED: &str = "engine_stopped";
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum Response {
+    Ok {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tx: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        accounts: Option<Vec<AccountRecord>>,
+    },
+    Error {
+        code: &'static str,
+        message: String,
+    },
+}
+
+impl Response {
+    fn tx(tx: u32) -> Self {
+        Response::Ok {
+            tx: Some(tx),
+            accounts: None,
+        }
+    }
+
+    fn error(code: &'static str, message: impl ToString) -> Self {
+        Response::Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<EngineStopped> for Response {
+    fn from(e: EngineStopped) -> Self {
+        Response::error(ENGINE_STOPPED, e)
+    }
+}
+
+/// Accepts connections on `listener` until the returned future is dropped,
+/// serving each one on its own task. Transactions are read under `policy`.
+pub async fn serve(
+    listener: TcpListener,
+    handle: EngineHandle,
+    policy: IngestPolicy,
+) -> io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let handle = handle.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, handle, policy).await {
+                eprintln!("Connection {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    handle: EngineHandle,
+    policy: IngestPolicy,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut header = StringRecord::from(DEFAULT_HEADER.to_vec());
+
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+
+        let response = if line.is_empty() {
+            continue;
+        } else if line.starts_with('{') {
+            match serde_json::from_str::<CsvTransaction>(line) {
+                Ok(csv_tx) => apply(&handle, csv_tx, &policy).await,
+                Err(e) => Response::error(MALFORMED_RECORD, e),
+            }
+        } else if let Some(query) = parse_query(line) {
+            query_accounts(&handle, query).await
+        } else {
+            match parse_csv_line(line) {
+                Ok(record) if record.iter().any(|field| field == "type") => {
+                    header = record;
+                    continue;
+                }
+                Ok(record) => match record.deserialize::<CsvTransaction>(Some(&header)) {
+                    Ok(csv_tx) => apply(&handle, csv_tx, &policy).await,
+                    Err(_) if record.len() == 1 => {
+                        Response::error(UNKNOWN_REQUEST, format!("Unknown request '{}'", line))
+                    }
+                    Err(e) => Response::error(MALFORMED_RECORD, e),
+                },
+                Err(e) => Response::error(MALFORMED_RECORD, e),
+            }
+        };
+
+        let mut out = serde_json::to_vec(&response)?;
+        out.push(b'\n');
+        writer.write_all(&out).await?;
+    }
+
+    Ok(())
+}
+
+enum Query {
+    Account(u16),
+    Accounts,
+}
+
+fn parse_query(line: &str) -> Option<Query> {
+    let mut words = line.split_whitespace();
+
+    match (words.next()?, words.next(), words.next()) {
+        ("accounts", None, None) => Some(Query::Accounts),
+        ("account", Some(client), None) => client.parse().ok().map(Query::Account),
+        _ => None,
+    }
+}
+
+fn parse_csv_line(line: &str) -> csv::Result<StringRecord> {
+    let mut record = StringRecord::new();
+    ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes())
+        .read_record(&mut record)?;
+
+    Ok(record)
+}
+
+async fn apply(handle: &EngineHandle, csv_tx: CsvTransaction, policy: &IngestPolicy) -> Response {
+    let tx_id = csv_tx.tx;
+
+    let tx = match csv_tx.into_transaction(policy) {
+        Ok(tx) => tx,
+        Err(e) => return Response::error(e.code(), e),
+    };
+
+    match handle.apply(tx).await {
+        Ok(Ok(())) => Response::tx(tx_id),
+        Ok(Err(e)) => Response::error(e.code(), e),
+        Err(e) => e.into(),
+    }
+}
+
+async fn query_accounts(handle: &EngineHandle, query: Query) -> Response {
+    match query {
+        Query::Account(client) => match handle.account(client).await {
+            Ok(Some(account)) => Response::Ok {
+                tx: None,
+                accounts: Some(AccountRecord::rows(&account)),
+            },
+            Ok(None) => {
+                let e = EngineError::NonExistentClient(client);
+                Response::error(e.code(), e)
+            }
+            Err(e) => e.into(),
+        },
+        Query::Accounts => match handle.accounts().await {
+            Ok(accounts) => Response::Ok {
+                tx: None,
+                accounts: Some(accounts.iter().flat_map(AccountRecord::rows).collect()),
+            },
+            Err(e) => e.into(),
+        },
+    }
+}
@@ -0,0 +1,413 @@
+use crate::engine::{ClientState, Engine};
+use crate::error::EngineError;
+use crate::policy::EnginePolicy;
+use crate::rejection::Rejection;
+use crate::transaction::{Transaction, TransactionType};
+use crate::Origin;
+
+use std::collections::HashMap;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// Runs one `Engine` per shard, each on its own task with its own channel.
+///
+/// Transactions are routed by client, so the shards share no account state.
+/// The one piece of global state is transaction id ownership: ids are unique
+/// across clients, which no single shard can check. Shards report back whether each
+/// deposit, withdrawal and transfer was applied, and the dispatcher records
+/// the client of every applied one in `owners` and rejects other clients
+/// reusing its id. While an id is still in flight on one shard, a transaction
+/// of another client using it waits for the verdict, so a rejected transaction
+/// never keeps its id from a later one. Like the stored transactions of an
+/// `Engine`, `owners` grows with every applied id.
+///
+/// A transfer between clients of two shards, and any dispute, resolve or
+/// chargeback of it, touches both. The shard of the destination lends it to
+/// the shard of the sender once it has applied everything sent before, and
+/// waits for it back, while the sender's shard applies the transaction as a
+/// single `Engine` would. Neither client is touched by anything else in the
+/// meantime, so the results are those of a single `Engine` fed the same
+/// input. `transfers` keeps the destination of every such transfer sent.
+pub struct ShardedEngine {
+    shards: Vec<mpsc::Sender<ShardMessage>>,
+    handles: Vec<JoinHandle<Engine>>,
+    policy: EnginePolicy,
+    owners: HashMap<u32, u16>,
+    /// Ids sent to a shard and not yet decided on, with their client and how
+    /// many of its transactions are in flight.
+    pending: HashMap<u32, (u16, usize)>,
+    verdicts: Option<mpsc::UnboundedReceiver<Verdict>>,
+    /// Destination of every transfer between clients of different shards.
+    transfers: HashMap<u32, u16>,
+    rejections: Option<mpsc::Sender<Rejection>>,
+}
+
+enum ShardMessage {
+    Apply(Origin, Transaction),
+    /// Applies a transaction that also touches `client` of another shard,
+    /// once that shard has lent it.
+    ApplyWith {
+        origin: Origin,
+        tx: Transaction,
+        client: u16,
+        lent: oneshot::Receiver<ClientState>,
+        returned: oneshot::Sender<ClientState>,
+    },
+    /// Lends `client` to another shard and waits for it back.
+    Lend {
+        client: u16,
+        lent: oneshot::Sender<ClientState>,
+        returned: oneshot::Receiver<ClientState>,
+    },
+}
+
+/// Whether a shard applied a deposit, withdrawal or transfer, and with it took
+/// its id.
+#[derive(Debug)]
+struct Verdict {
+    tx_id: u32,
+    client: u16,
+    applied: bool,
+}
+
+impl ShardedEngine {
+    /// Spawns `shards` empty engine tasks. Rejected transactions are logged to
+    /// stderr and, when given, also sent to `rejections`.
+    pub fn new(
+        shards: usize,
+        channel_size: usize,
+        rejections: Option<mpsc::Sender<Rejection>>,
+    ) -> Self {
+        Self::from_engine(Engine::default(), shards, channel_size, rejections)
+    }
+
+    /// Like `ShardedEngine::new` but the shards start from the state of
+    /// `engine`, e.g. one restored from a snapshot.
+    pub fn from_engine(
+        engine: Engine,
+        shards: usize,
+        channel_size: usize,
+        rejections: Option<mpsc::Sender<Rejection>>,
+    ) -> Self {
+        assert!(shards > 0, "a sharded engine needs at least one shard");
+        assert!(
+            shards == 1 || !engine.has_wal(),
+            "a write-ahead log can only be used with a single shard"
+        );
+
+        // With a single shard the engine sees every id and client itself
+        let (owners, transfers, verdict_sender, verdicts) = if shards > 1 {
+            let owners = engine
+                .transactions()
+                .map(|stored| (stored.tx.tx_id, stored.tx.client))
+                .collect();
+            let transfers = engine
+                .transactions()
+                .filter_map(|stored| {
+                    let to = stored.tx.to?;
+                    let crosses = shard_for(to, shards) != shard_for(stored.tx.client, shards);
+                    crosses.then_some((stored.tx.tx_id, to))
+                })
+                .collect();
+            // Unbounded so a shard never waits on a dispatcher that is itself
+            // waiting for room in the shard's channel
+            let (sender, rx) = mpsc::unbounded_channel();
+            (owners, transfers, Some(sender), Some(rx))
+        } else {
+            (HashMap::new(), HashMap::new(), None, None)
+        };
+
+        let policy = engine.policy();
+        let (senders, handles) = engine
+            .split(shards, |client| shard_for(client, shards))
+            .into_iter()
+            .map(|engine| {
+                let (sender, rx) = mpsc::channel(channel_size);
+                let handle = tokio::spawn(run_shard(
+                    engine,
+                    rx,
+                    verdict_sender.clone(),
+                    rejections.clone(),
+                ));
+                (sender, handle)
+            })
+            .unzip();
+
+        Self {
+            shards: senders,
+            handles,
+            policy,
+            owners,
+            pending: HashMap::new(),
+            verdicts,
+            transfers,
+            rejections,
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Index of the shard owning `client`.
+    pub fn shard_for(&self, client: u16) -> usize {
+        shard_for(client, self.shards.len())
+    }
+
+    /// Routes `tx` to the shard owning its client, along with the destination
+    /// of the transfer it is or references if that lives on another shard.
+    pub async fn send(&mut self, origin: Origin, tx: Transaction) {
+        if let Err(e) = self.check_owner(&tx).await {
+            report(&self.rejections, origin, tx.client, tx.tx_id, e).await;
+            return;
+        }
+
+        let shard = self.shard_for(tx.client);
+        let message = match self.crossing(&tx) {
+            Some(to) => {
+                let (lend, lent) = oneshot::channel();
+                let (give_back, returned) = oneshot::channel();
+                let lender = self.shard_for(to);
+                self.shards[lender]
+                    .send(ShardMessage::Lend {
+                        client: to,
+                        lent: lend,
+                        returned,
+                    })
+                    .await
+                    .expect("Shard dropped");
+
+                ShardMessage::ApplyWith {
+                    origin,
+                    tx,
+                    client: to,
+                    lent,
+                    returned: give_back,
+                }
+            }
+            None => ShardMessage::Apply(origin, tx),
+        };
+
+        self.shards[shard]
+            .send(message)
+            .await
+            .expect("Shard dropped");
+    }
+
+    /// The client on another shard `tx` touches, if any.
+    fn crossing(&mut self, tx: &Transaction) -> Option<u16> {
+        if self.shards.len() == 1 {
+            return None;
+        }
+
+        match tx.kind {
+            TransactionType::Transfer => {
+                let to = tx.to?;
+                if self.shard_for(to) == self.shard_for(tx.client) {
+                    return None;
+                }
+                self.transfers.insert(tx.tx_id, to);
+                Some(to)
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                self.transfers.get(&tx.tx_id).copied()
+            }
+            TransactionType::Deposit | TransactionType::Withdrawal => None,
+        }
+    }
+
+    /// Closes every shard, waits for them to drain and merges them back into a
+    /// single `Engine`.
+    pub async fn finish(self) -> Engine {
+        drop(self.shards);
+
+        let mut engines = Vec::with_capacity(self.handles.len());
+        for handle in self.handles {
+            engines.push(handle.await.expect("Shard panicked"));
+        }
+
+        if engines.len() == 1 {
+            return engines.pop().unwrap();
+        }
+
+        let mut engine = Engine::default().with_policy(self.policy);
+        for shard in engines {
+            engine.merge(shard);
+        }
+
+        engine
+    }
+
+    async fn check_owner(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        if self.shards.len() == 1 {
+            return Ok(());
+        }
+
+        let verdicts = self.verdicts.as_mut().expect("shards report verdicts");
+        while let Ok(verdict) = verdicts.try_recv() {
+            record_verdict(&mut self.owners, &mut self.pending, verdict);
+        }
+
+        // Only the shard of another client can tell whether that client took
+        // the id, transactions of the same client are kept in order anyway
+        while let Some(&(client, _)) = self.pending.get(&tx.tx_id) {
+            if client == tx.client {
+                break;
+            }
+            let verdict = verdicts.recv().await.expect("Shard dropped");
+            record_verdict(&mut self.owners, &mut self.pending, verdict);
+        }
+
+        let owner = self.owners.get(&tx.tx_id).copied();
+        match tx.kind {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer => {
+                match owner {
+                    Some(owner) if owner != tx.client => {
+                        return Err(EngineError::DuplicateTransaction(tx.tx_id));
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.pending.entry(tx.tx_id).or_insert((tx.client, 0)).1 += 1;
+                    }
+                }
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                if let Some(owner) = owner.filter(|&owner| owner != tx.client) {
+                    return Err(EngineError::InvalidClient(tx.client, owner));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn shard_for(client: u16, shards: usize) -> usize {
+    client as usize % shards
+}
+
+/// Settles one in-flight id, handing it to its client if it was applied.
+fn record_verdict(
+    owners: &mut HashMap<u32, u16>,
+    pending: &mut HashMap<u32, (u16, usize)>,
+    verdict: Verdict,
+) {
+    if verdict.applied {
+        owners.insert(verdict.tx_id, verdict.client);
+    }
+
+    if let Some((_, in_flight)) = pending.get_mut(&verdict.tx_id) {
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            pending.remove(&verdict.tx_id);
+        }
+    }
+}
+
+async fn run_shard(
+    mut engine: Engine,
+    mut rx: mpsc::Receiver<ShardMessage>,
+    verdicts: Option<mpsc::UnboundedSender<Verdict>>,
+    rejections: Option<mpsc::Sender<Rejection>>,
+) -> Engine {
+    while let Some(message) = rx.recv().await {
+        let (origin, tx) = match message {
+            ShardMessage::Apply(origin, tx) => (origin, tx),
+            ShardMessage::ApplyWith {
+                origin,
+                tx,
+                client,
+                lent,
+                returned,
+            } => {
+                let state = lent.await.expect("Lending shard dropped");
+                engine.put_client(client, state);
+
+                let (sender, tx_id, claims_id) = (tx.client, tx.tx_id, tx.kind.carries_amount());
+                let result = engine.apply_transaction_at(tx, &origin);
+
+                // The lender waits for it, so it is only gone if the lender is
+                let _ = returned.send(engine.take_client(client));
+
+                settle(
+                    &verdicts,
+                    &rejections,
+                    origin,
+                    sender,
+                    tx_id,
+                    claims_id,
+                    result,
+                )
+                .await;
+                continue;
+            }
+            ShardMessage::Lend {
+                client,
+                lent,
+                returned,
+            } => {
+                // Nothing else touches the client until it is back
+                if lent.send(engine.take_client(client)).is_ok() {
+                    let state = returned.await.expect("Borrowing shard dropped");
+                    engine.put_client(client, state);
+                }
+                continue;
+            }
+        };
+
+        let (client, tx_id, claims_id) = (tx.client, tx.tx_id, tx.kind.carries_amount());
+        let result = engine.apply_transaction_at(tx, &origin);
+        settle(
+            &verdicts,
+            &rejections,
+            origin,
+            client,
+            tx_id,
+            claims_id,
+            result,
+        )
+        .await;
+    }
+
+    engine
+}
+
+/// Tells the dispatcher whether a transaction claiming `tx_id` took it, and
+/// reports it if it was rejected.
+async fn settle(
+    verdicts: &Option<mpsc::UnboundedSender<Verdict>>,
+    rejections: &Option<mpsc::Sender<Rejection>>,
+    origin: Origin,
+    client: u16,
+    tx_id: u32,
+    claims_id: bool,
+    result: Result<(), EngineError>,
+) {
+    if let Some(verdicts) = verdicts.as_ref().filter(|_| claims_id) {
+        let verdict = Verdict {
+            tx_id,
+            client,
+            applied: result.is_ok(),
+        };
+        // The dispatcher only stops listening once it is finishing
+        let _ = verdicts.send(verdict);
+    }
+
+    if let Err(e) = result {
+        report(rejections, origin, client, tx_id, e).await;
+    }
+}
+
+async fn report(
+    rejections: &Option<mpsc::Sender<Rejection>>,
+    origin: Origin,
+    client: u16,
+    tx_id: u32,
+    e: EngineError,
+) {
+    eprintln!("Engine error: {:?}", e);
+    if let Some(rejections) = rejections {
+        let rejection = Rejection::engine(origin, client, tx_id, &e);
+        rejections.send(rejection).await.expect("Receiver dropped");
+    }
+}
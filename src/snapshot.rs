@@ -0,0 +1,133 @@
+//! On-disk format for `Engine::snapshot` and `Engine::restore`.
+//!
+//! A snapshot is NDJSON: a header line carrying the format version and the
+//! number of write-ahead log records the state holds, see `crate::wal`,
+//! followed by one line per account, one line per admin action, one line per stored
+//! transaction and one line per ledger entry, e.g.
+//!
+//! ```text
+//! {"version":1,"wal_position":3}
+//! {"type":"account","client":1,"balances":{"USD":{"available":"50","held":"50","total":"100"}},"locked":false,"closed":false}
+//! {"type":"admin","client":1,"action":"freeze","operator":"jo","reason":"suspected fraud"}
+//! {"type":"transaction","tx":{"client":1,"tx_id":1,"kind":"deposit","amount":"100","currency":"USD"},"state":"disputed","held":"50"}
+//! {"type":"entry","tx":1,"kind":"deposit","currency":"USD","debit":"external","credit":"available:1","amount":"100"}
+//! {"type":"entry","tx":1,"kind":"dispute","currency":"USD","debit":"available:1","credit":"held:1","amount":"50"}
+//! ```
+//!
+//! Being line based the snapshot is written and read as a stream, without ever
+//! holding a serialized copy of the whole engine in memory.
+
+use crate::account::Account;
+use crate::admin::AdminEvent;
+use crate::error::SnapshotError;
+use crate::ledger::Entry;
+use crate::transaction::StoredTransaction;
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+
+/// Version written to new snapshots, bumped on any incompatible change.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SnapshotHeader {
+    pub version: u32,
+    /// Write-ahead log records, counted from the first ever logged, whose
+    /// effect the snapshot already holds.
+    #[serde(default)]
+    pub wal_position: u64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SnapshotRecord {
+    Account(Account),
+    Admin(AdminEvent),
+    Transaction(StoredTransaction),
+    Entry(Entry),
+}
+
+/// Borrowing twin of `SnapshotRecord` so writing never clones the engine state.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SnapshotRecordRef<'a> {
+    Account(&'a Account),
+    Admin(&'a AdminEvent),
+    Transaction(&'a StoredTransaction),
+    Entry(&'a Entry),
+}
+
+pub fn write_snapshot<'a, W: Write>(
+    mut writer: W,
+    wal_position: u64,
+    accounts: impl Iterator<Item = &'a Account>,
+    admin_log: impl Iterator<Item = &'a AdminEvent>,
+    transactions: impl Iterator<Item = &'a StoredTransaction>,
+    entries: impl Iterator<Item = &'a Entry>,
+) -> io::Result<()> {
+    write_line(
+        &mut writer,
+        &SnapshotHeader {
+            version: SNAPSHOT_VERSION,
+            wal_position,
+        },
+    )?;
+
+    for account in accounts {
+        write_line(&mut writer, &SnapshotRecordRef::Account(account))?;
+    }
+
+    for event in admin_log {
+        write_line(&mut writer, &SnapshotRecordRef::Admin(event))?;
+    }
+
+    for transaction in transactions {
+        write_line(&mut writer, &SnapshotRecordRef::Transaction(transaction))?;
+    }
+
+    for entry in entries {
+        write_line(&mut writer, &SnapshotRecordRef::Entry(entry))?;
+    }
+
+    writer.flush()
+}
+
+/// Checks the header and returns it along with the records that follow it.
+pub fn read_snapshot<R: BufRead>(
+    reader: R,
+) -> Result<
+    (
+        SnapshotHeader,
+        impl Iterator<Item = Result<SnapshotRecord, SnapshotError>>,
+    ),
+    SnapshotError,
+> {
+    let mut lines = reader.lines().enumerate();
+
+    let header = match lines.next() {
+        Some((_, line)) => line?,
+        None => return Err(SnapshotError::MissingHeader),
+    };
+    let header: SnapshotHeader = serde_json::from_str(&header)
+        .map_err(|source| SnapshotError::InvalidRecord { line: 1, source })?;
+
+    if header.version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(header.version));
+    }
+
+    let records = lines
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?).map_err(|source| SnapshotError::InvalidRecord {
+                line: index + 1,
+                source,
+            })
+        });
+
+    Ok((header, records))
+}
+
+fn write_line<W: Write, T: Serialize>(writer: &mut W, value: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writeln!(writer)
+}
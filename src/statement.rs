@@ -0,0 +1,420 @@
+//! Account statements, what happened to one client over part of the input.
+//!
+//! A statement is built by replaying transactions through an `Engine`, each
+//! tagged with its position in the input, counting from 1. For the rows in
+//! range, picked by position or by timestamp, it reports the client's balances
+//! before the first of them, every transaction that touched the client with
+//! its effect on `available` and `held`, and the balances after the last of
+//! them. Transactions the engine rejects have no effect and are left out, as
+//! are rows past the range, which are not replayed at all.
+//!
+//! By timestamp, each row is taken to happen at the latest timestamp seen so
+//! far. A row without a timestamp, or stamped earlier than a row before it,
+//! falls in with the rows around it, so the rows in range are always one run
+//! of the input and the balances add up from one line to the next. Rows before
+//! the first timestamp come before any time, so they are only in a range left
+//! open at its start.
+
+use crate::account::Balance;
+use crate::currency::Currency;
+use crate::engine::Engine;
+use crate::output::{OutputFormat, OUTPUT_PRECISION};
+use crate::transaction::{Transaction, TransactionType};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// The rows a statement covers, both ends included. A missing end leaves the
+/// range open on that side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatementRange {
+    /// Input positions, counting from 1.
+    Positions { from: Option<u64>, to: Option<u64> },
+    /// Timestamps, in seconds since the Unix epoch, see the module docs.
+    Time { from: Option<u64>, to: Option<u64> },
+}
+
+impl Default for StatementRange {
+    fn default() -> Self {
+        StatementRange::Positions {
+            from: None,
+            to: None,
+        }
+    }
+}
+
+impl StatementRange {
+    pub fn from(&self) -> Option<u64> {
+        match *self {
+            StatementRange::Positions { from, .. } | StatementRange::Time { from, .. } => from,
+        }
+    }
+
+    pub fn to(&self) -> Option<u64> {
+        match *self {
+            StatementRange::Positions { to, .. } | StatementRange::Time { to, .. } => to,
+        }
+    }
+
+    /// Whether a row at `at`, a position or a time as the range is, falls in
+    /// it. A row at no time comes before every time.
+    pub fn contains(&self, at: Option<u64>) -> bool {
+        let started = match at {
+            Some(at) => self.from().is_none_or(|from| at >= from),
+            None => self.from().is_none(),
+        };
+        started && !self.ends_before(at)
+    }
+
+    /// Whether the range is over by `at`, so the rest need not be read.
+    pub fn ends_before(&self, at: Option<u64>) -> bool {
+        at.zip(self.to()).is_some_and(|(at, to)| at > to)
+    }
+}
+
+/// A transaction on the statement and the balance it left behind in its
+/// currency.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementLine {
+    pub position: u64,
+    pub tx: u32,
+    pub kind: TransactionType,
+    pub currency: Currency,
+    /// Change to `available`, negative when funds left it.
+    pub available: Decimal,
+    /// Change to `held`.
+    pub held: Decimal,
+    pub balance: Balance,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub client: u16,
+    pub range: StatementRange,
+    pub opening: BTreeMap<Currency, Balance>,
+    pub lines: Vec<StatementLine>,
+    pub closing: BTreeMap<Currency, Balance>,
+}
+
+impl Statement {
+    /// Replays `transactions` on top of `engine` and returns the statement of
+    /// `client` over `range`. `engine` holds the state before the first
+    /// position, e.g. a restored snapshot, and must not have a write-ahead log.
+    pub fn replay(
+        engine: Engine,
+        client: u16,
+        range: StatementRange,
+        transactions: impl IntoIterator<Item = (u64, Transaction)>,
+    ) -> Self {
+        let mut builder = StatementBuilder::new(engine, client, range);
+        for (position, tx) in transactions {
+            builder.replay(position, tx);
+        }
+        builder.finish()
+    }
+
+    /// Writes the statement as `format`, see `write_csv` and `write_json`.
+    /// NDJSON gets the same single object as JSON.
+    pub fn write<W: Write>(&self, writer: W, format: OutputFormat) -> io::Result<()> {
+        match format {
+            OutputFormat::Csv => self.write_csv(writer),
+            OutputFormat::Json | OutputFormat::Ndjson => self.write_json(writer),
+        }
+    }
+
+    /// Writes one row per opening balance, transaction and closing balance, in
+    /// that order, under a
+    /// `line,position,tx,type,currency,available_change,held_change,available,held,total`
+    /// header. `line` is `opening`, `transaction` or `closing` and balance rows
+    /// leave the transaction columns empty.
+    pub fn write_csv<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+
+        let rows = CsvRow::balances(LineKind::Opening, &self.opening)
+            .chain(self.lines.iter().map(CsvRow::transaction))
+            .chain(CsvRow::balances(LineKind::Closing, &self.closing));
+
+        for row in rows {
+            writer.serialize(row).map_err(io::Error::from)?;
+        }
+        writer.flush()
+    }
+
+    /// Writes a single JSON object on one line holding the client, the range,
+    /// `from` and `to` counting what `by` says, `position` or `time`,
+    /// `opening` and `closing` balances with one record per currency and the
+    /// `transactions` in between.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let balances = |balances: &BTreeMap<Currency, Balance>| {
+            balances
+                .iter()
+                .map(|(currency, balance)| BalanceRecord::new(*currency, balance))
+                .collect()
+        };
+        let record = StatementRecord {
+            client: self.client,
+            by: match self.range {
+                StatementRange::Positions { .. } => RangeKind::Position,
+                StatementRange::Time { .. } => RangeKind::Time,
+            },
+            from: self.range.from(),
+            to: self.range.to(),
+            opening: balances(&self.opening),
+            transactions: self.lines.iter().map(LineRecord::from).collect(),
+            closing: balances(&self.closing),
+        };
+
+        serde_json::to_writer(&mut writer, &record)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+/// Builds a `Statement` one transaction at a time, for input that is streamed
+/// rather than held in memory. Positions must be replayed in increasing order.
+pub struct StatementBuilder {
+    engine: Engine,
+    client: u16,
+    range: StatementRange,
+    /// The latest timestamp replayed so far.
+    clock: Option<u64>,
+    opening: Option<BTreeMap<Currency, Balance>>,
+    lines: Vec<StatementLine>,
+}
+
+impl StatementBuilder {
+    pub fn new(engine: Engine, client: u16, range: StatementRange) -> Self {
+        Self {
+            engine,
+            client,
+            range,
+            clock: None,
+            opening: None,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Whether the range is over by `position`, whatever is read there, so the
+    /// rest of the input need not be read.
+    pub fn ends_before(&self, position: u64) -> bool {
+        match self.range {
+            StatementRange::Positions { .. } => self.range.ends_before(Some(position)),
+            StatementRange::Time { .. } => self.range.ends_before(self.clock),
+        }
+    }
+
+    /// Applies `tx`, read at `position`, recording it if it falls in the range
+    /// and touched the client.
+    pub fn replay(&mut self, position: u64, tx: Transaction) {
+        let at = match self.range {
+            StatementRange::Positions { .. } => Some(position),
+            StatementRange::Time { .. } => {
+                self.clock = self.clock.max(tx.timestamp);
+                self.clock
+            }
+        };
+        if self.range.ends_before(at) {
+            return;
+        }
+
+        let in_range = self.range.contains(at);
+        if in_range && self.opening.is_none() {
+            self.opening = Some(self.balances());
+        }
+
+        // Disputes, resolves and chargebacks act in the currency of the
+        // transaction they reference and, for a transfer, on its destination
+        let original = match tx.kind {
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                self.engine.transaction(tx.tx_id).map(|stored| &stored.tx)
+            }
+            _ => Some(&tx),
+        };
+        let currency = original.map_or(tx.currency, |original| original.currency);
+        let touches = tx.client == self.client
+            || original.is_some_and(|original| original.to == Some(self.client));
+
+        let (tx_id, kind) = (tx.tx_id, tx.kind.clone());
+        let before = self.balance(currency);
+        if self.engine.apply_transaction(tx).is_err() || !in_range || !touches {
+            return;
+        }
+        let after = self.balance(currency);
+
+        self.lines.push(StatementLine {
+            position,
+            tx: tx_id,
+            kind,
+            currency,
+            available: after.available - before.available,
+            held: after.held - before.held,
+            balance: after,
+        });
+    }
+
+    /// The statement so far. If no position in range was replayed, the opening
+    /// balances are the closing ones.
+    pub fn finish(self) -> Statement {
+        let closing = self.balances();
+
+        Statement {
+            client: self.client,
+            range: self.range,
+            opening: self.opening.unwrap_or_else(|| closing.clone()),
+            lines: self.lines,
+            closing,
+        }
+    }
+
+    fn balances(&self) -> BTreeMap<Currency, Balance> {
+        self.engine
+            .account(self.client)
+            .map(|account| account.balances.clone())
+            .unwrap_or_default()
+    }
+
+    fn balance(&self, currency: Currency) -> Balance {
+        self.engine
+            .account(self.client)
+            .map(|account| account.balance(currency))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum LineKind {
+    Opening,
+    Transaction,
+    Closing,
+}
+
+#[derive(Serialize)]
+struct CsvRow {
+    line: LineKind,
+    position: Option<u64>,
+    tx: Option<u32>,
+    #[serde(rename = "type")]
+    kind: Option<TransactionType>,
+    currency: Currency,
+    available_change: Option<Decimal>,
+    held_change: Option<Decimal>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+}
+
+impl CsvRow {
+    fn balances(
+        line: LineKind,
+        balances: &BTreeMap<Currency, Balance>,
+    ) -> impl Iterator<Item = Self> + '_ {
+        balances
+            .iter()
+            .map(move |(currency, balance)| Self::balance(line, *currency, balance))
+    }
+
+    fn balance(line: LineKind, currency: Currency, balance: &Balance) -> Self {
+        let record = BalanceRecord::new(currency, balance);
+        Self {
+            line,
+            position: None,
+            tx: None,
+            kind: None,
+            currency,
+            available_change: None,
+            held_change: None,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+        }
+    }
+
+    fn transaction(line: &StatementLine) -> Self {
+        let record = LineRecord::from(line);
+        Self {
+            line: LineKind::Transaction,
+            position: Some(record.position),
+            tx: Some(record.tx),
+            kind: Some(record.kind),
+            currency: record.currency,
+            available_change: Some(record.available_change),
+            held_change: Some(record.held_change),
+            available: record.available,
+            held: record.held,
+            total: record.total,
+        }
+    }
+}
+
+/// A balance rounded for output.
+#[derive(Serialize)]
+struct BalanceRecord {
+    currency: Currency,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+}
+
+impl BalanceRecord {
+    fn new(currency: Currency, balance: &Balance) -> Self {
+        Self {
+            currency,
+            available: balance.available.round_dp(OUTPUT_PRECISION),
+            held: balance.held.round_dp(OUTPUT_PRECISION),
+            total: balance.total.round_dp(OUTPUT_PRECISION),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LineRecord {
+    position: u64,
+    tx: u32,
+    #[serde(rename = "type")]
+    kind: TransactionType,
+    currency: Currency,
+    available_change: Decimal,
+    held_change: Decimal,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+}
+
+impl From<&StatementLine> for LineRecord {
+    fn from(line: &StatementLine) -> Self {
+        let balance = BalanceRecord::new(line.currency, &line.balance);
+        Self {
+            position: line.position,
+            tx: line.tx,
+            kind: line.kind.clone(),
+            currency: line.currency,
+            available_change: line.available.round_dp(OUTPUT_PRECISION),
+            held_change: line.held.round_dp(OUTPUT_PRECISION),
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum RangeKind {
+    Position,
+    Time,
+}
+
+#[derive(Serialize)]
+struct StatementRecord {
+    client: u16,
+    /// What `from` and `to` count.
+    by: RangeKind,
+    from: Option<u64>,
+    to: Option<u64>,
+    opening: Vec<BalanceRecord>,
+    transactions: Vec<LineRecord>,
+    closing: Vec<BalanceRecord>,
+}
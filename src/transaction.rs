@@ -1,14 +1,28 @@
+use crate::currency::Currency;
 use crate::error::EngineError;
+use crate::policy::IngestPolicy;
 
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Transaction {
     pub client: u16,
     pub tx_id: u32,
     pub kind: TransactionType,
     pub amount: Option<Decimal>,
+    /// Ignored on disputes, resolves and chargebacks, which always act on the
+    /// currency of the transaction they reference.
+    #[serde(default)]
+    pub currency: Currency,
+    /// Client credited by a transfer, `client` being the one debited. Unused
+    /// by every other kind.
+    #[serde(default)]
+    pub to: Option<u16>,
+    /// When the transaction happened, in seconds since the Unix epoch. Only
+    /// used to check disputes against the dispute window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -18,35 +32,62 @@ pub struct CsvTransaction {
     pub client: u16,
     pub tx: u32,
     pub amount: Option<Decimal>,
+    pub currency: Option<Currency>,
+    pub to: Option<u16>,
+    pub timestamp: Option<u64>,
 }
 
-impl TryFrom<CsvTransaction> for Transaction {
-    type Error = EngineError;
+impl CsvTransaction {
+    /// Turns the row into a transaction, checking and scaling its amount as
+    /// `policy` says.
+    pub fn into_transaction(self, policy: &IngestPolicy) -> Result<Transaction, EngineError> {
+        if self.kind.carries_amount() && self.amount.is_none() {
+            return Err(EngineError::MissingAmount(self.tx));
+        }
+        if self.kind == TransactionType::Transfer && self.to.is_none() {
+            return Err(EngineError::MissingDestination(self.tx));
+        }
 
-    fn try_from(csv: CsvTransaction) -> Result<Self, Self::Error> {
-        // Validate amount presence for deposit/withdrawal
-        match csv.kind {
-            TransactionType::Deposit | TransactionType::Withdrawal => {
-                if csv.amount.is_none() {
-                    // TODO: probably should be a different error type
-                    return Err(EngineError::InvalidTransaction {
-                        message: format!("Missing amount for transaction {}", csv.tx),
-                    });
+        let amount = match self.amount {
+            Some(_) if !self.kind.carries_amount() => {
+                if policy.reject_reference_amounts {
+                    return Err(EngineError::UnexpectedAmount(self.tx));
                 }
+                // Ignored by the engine, so left as it came
+                self.amount
             }
-            _ => {}
-        }
+            Some(amount) => {
+                let amount = policy.scale_amount(self.tx, amount)?;
+                if policy.reject_non_positive && amount <= Decimal::ZERO {
+                    return Err(EngineError::NonPositiveAmount(self.tx));
+                }
+                Some(amount)
+            }
+            None => None,
+        };
 
         Ok(Transaction {
-            kind: csv.kind,
-            client: csv.client,
-            tx_id: csv.tx,
-            amount: csv.amount,
+            kind: self.kind,
+            client: self.client,
+            tx_id: self.tx,
+            amount,
+            currency: self.currency.unwrap_or_default(),
+            to: self.to,
+            timestamp: self.timestamp,
         })
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+impl TryFrom<CsvTransaction> for Transaction {
+    type Error = EngineError;
+
+    /// Converts with the default `IngestPolicy`.
+    fn try_from(csv: CsvTransaction) -> Result<Self, Self::Error> {
+        csv.into_transaction(&IngestPolicy::default())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -54,19 +95,96 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Transfer,
 }
 
-impl Transaction {
-    pub fn is_valid(&self) -> bool {
-        match self.kind {
-            TransactionType::Deposit | TransactionType::Withdrawal => {
-                self.amount.is_some() && self.amount.unwrap() > Decimal::ZERO
-            }
-            TransactionType::Dispute { .. }
-            | TransactionType::Resolve { .. }
-            | TransactionType::Chargeback { .. } => self.amount.is_none(),
+impl TransactionType {
+    /// Whether the kind moves an amount of its own, as opposed to referencing
+    /// the transaction whose amount it acts on.
+    pub fn carries_amount(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+        )
+    }
+}
+
+/// Lifecycle of a stored deposit, withdrawal or transfer with respect to
+/// disputes.
+///
+/// ```text
+/// Processed --dispute--> Disputed --resolve----> Resolved
+///                                 --chargeback-> ChargedBack
+/// ```
+///
+/// `Resolved` and `ChargedBack` are terminal, a transaction can only be
+/// disputed once.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl TransactionState {
+    /// Returns the state reached by applying `kind` to a transaction in this
+    /// state, or the error describing why the transition is illegal.
+    pub fn next(self, tx_id: u32, kind: &TransactionType) -> Result<Self, EngineError> {
+        use TransactionState::*;
+
+        match (self, kind) {
+            (
+                _,
+                TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer,
+            ) => Err(EngineError::DuplicateTransaction(tx_id)),
+            (Processed, TransactionType::Dispute) => Ok(Disputed),
+            (Processed, _) => Err(EngineError::NotDisputed(tx_id)),
+            (Disputed, TransactionType::Dispute) => Err(EngineError::AlreadyDisputed(tx_id)),
+            (Disputed, TransactionType::Resolve) => Ok(Resolved),
+            (Disputed, TransactionType::Chargeback) => Ok(ChargedBack),
+            (Resolved | ChargedBack, _) => Err(EngineError::DisputeClosed(tx_id)),
         }
     }
+}
+
+/// A deposit, withdrawal or transfer held by the engine so it can later be referenced
+/// by a dispute, resolve or chargeback.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StoredTransaction {
+    pub tx: Transaction,
+    pub state: TransactionState,
+    /// What the open dispute of the transaction actually holds, which can be
+    /// less than its amount. Zero unless the transaction is disputed.
+    #[serde(default)]
+    pub held: Decimal,
+}
+
+impl StoredTransaction {
+    pub fn new(tx: Transaction) -> Self {
+        Self {
+            tx,
+            state: TransactionState::Processed,
+            held: Decimal::ZERO,
+        }
+    }
+}
+
+impl Transaction {
+    /// Moves the transaction to `currency`, the constructors all use the
+    /// default currency.
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    /// Stamps the transaction with `timestamp`, in seconds since the Unix
+    /// epoch. The constructors leave it without one.
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
 
     pub fn new_deposit(client: u16, tx_id: u32, amount: Decimal) -> Self {
         if amount <= Decimal::ZERO {
@@ -77,6 +195,9 @@ impl Transaction {
             tx_id,
             kind: TransactionType::Deposit,
             amount: Some(amount),
+            currency: Currency::default(),
+            to: None,
+            timestamp: None,
         }
     }
 
@@ -89,6 +210,9 @@ impl Transaction {
             tx_id,
             kind: TransactionType::Withdrawal,
             amount: Some(amount),
+            currency: Currency::default(),
+            to: None,
+            timestamp: None,
         }
     }
 
@@ -98,6 +222,9 @@ impl Transaction {
             tx_id,
             kind: TransactionType::Dispute,
             amount: None,
+            currency: Currency::default(),
+            to: None,
+            timestamp: None,
         }
     }
 
@@ -107,6 +234,9 @@ impl Transaction {
             tx_id,
             kind: TransactionType::Resolve,
             amount: None,
+            currency: Currency::default(),
+            to: None,
+            timestamp: None,
         }
     }
 
@@ -116,6 +246,22 @@ impl Transaction {
             tx_id,
             kind: TransactionType::Chargeback,
             amount: None,
+            currency: Currency::default(),
+            to: None,
+            timestamp: None,
+        }
+    }
+
+    /// Moves `amount` from `client` to `to`.
+    pub fn new_transfer(client: u16, to: u16, tx_id: u32, amount: Decimal) -> Self {
+        Self {
+            client,
+            tx_id,
+            kind: TransactionType::Transfer,
+            amount: Some(amount),
+            currency: Currency::default(),
+            to: Some(to),
+            timestamp: None,
         }
     }
 }
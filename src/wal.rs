@@ -0,0 +1,296 @@
+//! Write-ahead log of accepted transactions.
+//!
+//! Every transaction the engine accepts is appended as one NDJSON line and
+//! synced to disk before the engine changes any account, so after a crash
+//! replaying the log on top of the state the run started from gives back
+//! exactly the accepted transactions, and nothing else. Admin actions are
+//! logged the same way, in line with the transactions around them.
+//!
+//! A transaction read from an input file is logged together with its input
+//! and row, e.g.
+//!
+//! ```text
+//! {"input":{"source":"transactions.csv","row":3,"offset":39},"tx":{"client":1,"tx_id":2,"kind":"deposit","amount":"100","currency":"USD","to":null}}
+//! ```
+//!
+//! so a rerun of the same input knows where the previous run got to and, with
+//! `ResumeFilter`, skips every row up to there instead of checking them again
+//! against a later state. Rows rejected after the last logged one were checked
+//! against the state the log rebuilds, so checking them again gives the same
+//! answer.
+//!
+//! Records are numbered from the first ever logged, and the log opens with a
+//! `{"start":n}` header giving the number of its first record. A snapshot
+//! saves how many records its state holds, so a log emptied after a snapshot
+//! starts where the snapshot left off, and replaying a log the snapshot has
+//! already taken in skips the records it covers instead of applying them
+//! twice.
+
+use crate::admin::AdminEvent;
+use crate::error::WalError;
+use crate::transaction::Transaction;
+use crate::Origin;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// A line of the log. Records are written untagged, so a log holding only
+/// transactions reads the same as it did before admin actions were logged.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum WalRecord {
+    /// A transaction read from an input file.
+    Input {
+        input: WalCursor,
+        tx: Transaction,
+    },
+    /// A transaction that came from anywhere else, e.g. a server connection.
+    Transaction(Transaction),
+    Admin(AdminEvent),
+}
+
+/// First line of the log.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct WalHeader {
+    /// Position of the first record in the log.
+    start: u64,
+}
+
+/// The position of a row of input in the log, see `Origin`. The byte offset
+/// of the row tells a rerun whether the input still holds the same rows up to
+/// there.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WalCursor {
+    pub source: String,
+    pub row: u64,
+    pub offset: u64,
+}
+
+/// Borrowing twin of `WalRecord::Input` so appending never clones the row.
+#[derive(Serialize)]
+struct InputRecordRef<'a> {
+    input: CursorRef<'a>,
+    tx: &'a Transaction,
+}
+
+#[derive(Serialize)]
+struct CursorRef<'a> {
+    source: &'a str,
+    row: u64,
+    offset: u64,
+}
+
+/// Skips the rows of input a previous run already logged, up to and including
+/// the row at the cursor, the inputs being read in the same order as before.
+/// An input that no longer reaches the cursor's row at the same offset is
+/// refused rather than skipped.
+pub struct ResumeFilter {
+    cursor: Option<WalCursor>,
+    /// Whether the input the cursor points into has been started.
+    reached: bool,
+}
+
+impl ResumeFilter {
+    /// Resumes after `cursor`, which must name exactly one of `inputs`.
+    /// Without a cursor nothing is skipped.
+    pub fn new(cursor: Option<WalCursor>, inputs: &[String]) -> Result<Self, WalError> {
+        if let Some(cursor) = &cursor {
+            match inputs
+                .iter()
+                .filter(|&input| *input == cursor.source)
+                .count()
+            {
+                0 => {
+                    return Err(WalError::UnknownInput {
+                        input: cursor.source.clone(),
+                        row: cursor.row,
+                    })
+                }
+                1 => {}
+                _ => return Err(WalError::DuplicateInput(cursor.source.clone())),
+            }
+        }
+
+        Ok(Self {
+            cursor,
+            reached: false,
+        })
+    }
+
+    /// Whether the whole of the input `source`, about to be read, was already
+    /// processed. Must be asked of every input in turn.
+    pub fn skips_input(&mut self, source: &str) -> Result<bool, WalError> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(false);
+        };
+
+        // The input holding the cursor ended before its row
+        if self.reached {
+            return Err(self.changed());
+        }
+
+        self.reached = cursor.source == source;
+        Ok(!self.reached)
+    }
+
+    /// Whether the row at `origin`, of the input last passed to `skips_input`,
+    /// was already processed. Once the cursor's row is passed nothing more is
+    /// skipped.
+    pub fn skips(&mut self, origin: &Origin) -> Result<bool, WalError> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(false);
+        };
+
+        if origin.row < cursor.row {
+            return Ok(true);
+        }
+        if origin.row > cursor.row || origin.offset != cursor.offset {
+            return Err(self.changed());
+        }
+
+        self.cursor = None;
+        Ok(true)
+    }
+
+    /// Checks, once every input has been read, that the cursor was reached.
+    pub fn finish(self) -> Result<(), WalError> {
+        match self.cursor {
+            Some(_) => Err(self.changed()),
+            None => Ok(()),
+        }
+    }
+
+    fn changed(&self) -> WalError {
+        let cursor = self.cursor.as_ref().expect("only asked while resuming");
+        WalError::InputChanged {
+            input: cursor.source.clone(),
+            row: cursor.row,
+        }
+    }
+}
+
+pub struct Wal {
+    file: File,
+    len: u64,
+    start: Option<u64>,
+}
+
+impl Wal {
+    /// Opens the log at `path`, creating it if needed, and returns it along with
+    /// the records it already holds, the header aside. A torn final line, left
+    /// behind by a crash in the middle of an append, is dropped from the file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<(Wal, Vec<WalRecord>), WalError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut records = Vec::new();
+        let mut len = 0;
+        let mut reader = BufReader::new(&mut file);
+        let mut line = String::new();
+        let mut line_number = 0;
+        let mut start = None;
+
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            line_number += 1;
+            len += read as u64;
+
+            if line_number == 1 {
+                if let Ok(header) = serde_json::from_str::<WalHeader>(&line) {
+                    start = Some(header.start);
+                    continue;
+                }
+            }
+
+            let record = serde_json::from_str(&line).map_err(|source| WalError::InvalidRecord {
+                line: line_number,
+                source,
+            })?;
+            records.push(record);
+        }
+
+        // Anything after the last complete line never made it to disk whole
+        file.set_len(len)?;
+        file.seek(SeekFrom::Start(len))?;
+
+        Ok((Wal { file, len, start }, records))
+    }
+
+    /// Position of the first record in the log, if it has a header.
+    pub fn start(&self) -> Option<u64> {
+        self.start
+    }
+
+    /// Writes the header of an empty log, its first record to be at `start`.
+    pub fn begin(&mut self, start: u64) -> io::Result<()> {
+        self.append_line(&WalHeader { start })?;
+        self.start = Some(start);
+        Ok(())
+    }
+
+    /// Appends `tx`, read from the input at `origin` if given, and waits for it
+    /// to reach the disk. On failure the log is cut back to its previous length
+    /// so it never holds a partial record.
+    pub fn append(&mut self, tx: &Transaction, origin: Option<&Origin>) -> io::Result<()> {
+        match origin {
+            Some(origin) => self.append_line(&InputRecordRef {
+                input: CursorRef {
+                    source: &origin.source,
+                    row: origin.row,
+                    offset: origin.offset,
+                },
+                tx,
+            }),
+            None => self.append_line(tx),
+        }
+    }
+
+    /// Like `append` for an admin action.
+    pub fn append_admin(&mut self, event: &AdminEvent) -> io::Result<()> {
+        self.append_line(event)
+    }
+
+    /// Empties the log, once what it holds is safe elsewhere, e.g. in a
+    /// snapshot, the next record to be at `start`.
+    pub fn truncate(&mut self, start: u64) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.len = 0;
+        self.start = None;
+        self.begin(start)
+    }
+
+    fn append_line<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let result = self
+            .file
+            .write_all(&line)
+            .and_then(|_| self.file.sync_data());
+
+        match result {
+            Ok(()) => {
+                self.len += line.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                self.file.set_len(self.len)?;
+                self.file.seek(SeekFrom::Start(self.len))?;
+                Err(e)
+            }
+        }
+    }
+}
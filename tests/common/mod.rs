@@ -0,0 +1,19 @@
+//! Fixtures shared by the integration tests. Each test crate uses only some
+//! of them.
+#![allow(dead_code)]
+
+use octopi::engine::Engine;
+
+/// The accounts of `engine` as the full-precision CSV dump.
+pub fn dump(engine: &Engine) -> String {
+    let mut buf = Vec::new();
+    engine.dump_accounts(&mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+/// `engine` saved as a snapshot.
+pub fn snapshot(engine: &Engine) -> Vec<u8> {
+    let mut buf = Vec::new();
+    engine.snapshot(&mut buf).unwrap();
+    buf
+}
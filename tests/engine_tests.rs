@@ -1,6 +1,8 @@
 use octopi::account::Account;
+use octopi::currency::Currency;
 use octopi::engine::{chargeback, deposit, dispute, resolve, withdraw};
 use octopi::error::EngineError;
+use octopi::policy::DisputeHold;
 use octopi::transaction::Transaction;
 
 use rust_decimal::Decimal;
@@ -13,84 +15,88 @@ mod deposit_tests {
     #[test]
     fn test_deposit_basic_functionality() {
         let mut account = Account::new(1);
-        let result = deposit(&mut account, Decimal::from(100));
+        let result = deposit(&mut account, Currency::USD, Decimal::from(100));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(100));
-        assert_eq!(account.total, Decimal::from(100));
-        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
         assert!(!account.locked);
     }
 
     #[test]
     fn test_deposit_zero_amount() {
         let mut account = Account::new(1);
-        let result = deposit(&mut account, Decimal::ZERO);
+        let result = deposit(&mut account, Currency::USD, Decimal::ZERO);
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::ZERO);
-        assert_eq!(account.total, Decimal::ZERO);
-        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).total, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
     }
 
     #[test]
     fn test_deposit_negative_amount() {
         let mut account = Account::new(1);
-        let result = deposit(&mut account, Decimal::from(-50));
+        let result = deposit(&mut account, Currency::USD, Decimal::from(-50));
 
         assert!(result.is_err());
         match result {
-            Err(EngineError::InvalidTransaction { message }) => {
-                assert_eq!(message, "Total balance is negative");
+            Err(EngineError::NegativeBalance(client)) => {
+                assert_eq!(client, 1);
             }
-            _ => panic!("Expected InvalidTransaction error"),
+            _ => panic!("Expected NegativeBalance error"),
         }
     }
 
     #[test]
     fn test_deposit_negative_amount_on_existing_balance() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(25);
-        account.total = Decimal::from(25);
+        account.balance_mut(Currency::USD).available = Decimal::from(25);
+        account.balance_mut(Currency::USD).total = Decimal::from(25);
 
-        let result = deposit(&mut account, Decimal::from(-50));
+        let result = deposit(&mut account, Currency::USD, Decimal::from(-50));
 
         assert!(result.is_err());
         match result {
-            Err(EngineError::InvalidTransaction { message }) => {
-                assert_eq!(message, "Total balance is negative");
+            Err(EngineError::NegativeBalance(client)) => {
+                assert_eq!(client, 1);
             }
-            _ => panic!("Expected InvalidTransaction error"),
+            _ => panic!("Expected NegativeBalance error"),
         }
+
+        // A rejected deposit must not touch the balance
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(25));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(25));
     }
 
     #[test]
     fn test_deposit_existing_account() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(50);
-        account.total = Decimal::from(50);
+        account.balance_mut(Currency::USD).available = Decimal::from(50);
+        account.balance_mut(Currency::USD).total = Decimal::from(50);
 
-        let result = deposit(&mut account, Decimal::from(25));
+        let result = deposit(&mut account, Currency::USD, Decimal::from(25));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(75));
-        assert_eq!(account.total, Decimal::from(75));
-        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(75));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(75));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
     }
 
     #[test]
     fn test_deposit_preserves_held_amount() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.held = Decimal::from(50);
-        account.total = Decimal::from(150);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).held = Decimal::from(50);
+        account.balance_mut(Currency::USD).total = Decimal::from(150);
 
-        let result = deposit(&mut account, Decimal::from(25));
+        let result = deposit(&mut account, Currency::USD, Decimal::from(25));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(125));
-        assert_eq!(account.held, Decimal::from(50));
-        assert_eq!(account.total, Decimal::from(175));
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(125));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(175));
     }
 
     #[test]
@@ -98,11 +104,11 @@ mod deposit_tests {
         let mut account = Account::new(1);
         account.locked = true;
 
-        let result = deposit(&mut account, Decimal::from(100));
+        let result = deposit(&mut account, Currency::USD, Decimal::from(100));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(100));
-        assert_eq!(account.total, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
         assert!(account.locked); // Should remain locked
     }
 
@@ -111,11 +117,11 @@ mod deposit_tests {
         let mut account = Account::new(1);
         let large_amount = Decimal::from(1_000_000_000);
 
-        let result = deposit(&mut account, large_amount);
+        let result = deposit(&mut account, Currency::USD, large_amount);
 
         assert!(result.is_ok());
-        assert_eq!(account.available, large_amount);
-        assert_eq!(account.total, large_amount);
+        assert_eq!(account.balance(Currency::USD).available, large_amount);
+        assert_eq!(account.balance(Currency::USD).total, large_amount);
     }
 
     #[test]
@@ -123,11 +129,11 @@ mod deposit_tests {
         let mut account = Account::new(1);
         let decimal_amount = Decimal::from_str("123.45").unwrap();
 
-        let result = deposit(&mut account, decimal_amount);
+        let result = deposit(&mut account, Currency::USD, decimal_amount);
 
         assert!(result.is_ok());
-        assert_eq!(account.available, decimal_amount);
-        assert_eq!(account.total, decimal_amount);
+        assert_eq!(account.balance(Currency::USD).available, decimal_amount);
+        assert_eq!(account.balance(Currency::USD).total, decimal_amount);
     }
 
     #[test]
@@ -135,62 +141,76 @@ mod deposit_tests {
         let mut account = Account::new(1);
 
         // First deposit
-        let result1 = deposit(&mut account, Decimal::from(50));
+        let result1 = deposit(&mut account, Currency::USD, Decimal::from(50));
         assert!(result1.is_ok());
 
         // Second deposit
-        let result2 = deposit(&mut account, Decimal::from(75));
+        let result2 = deposit(&mut account, Currency::USD, Decimal::from(75));
         assert!(result2.is_ok());
 
         // Third deposit
-        let result3 = deposit(&mut account, Decimal::from(25));
+        let result3 = deposit(&mut account, Currency::USD, Decimal::from(25));
         assert!(result3.is_ok());
 
-        assert_eq!(account.available, Decimal::from(150));
-        assert_eq!(account.total, Decimal::from(150));
-        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(150));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(150));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
     }
 
     #[test]
     fn test_deposit_negative_amount_exactly_balances_existing() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
-        let result = deposit(&mut account, Decimal::from(-100));
+        let result = deposit(&mut account, Currency::USD, Decimal::from(-100));
 
         assert!(result.is_ok()); // Should be exactly 0, not negative
-        assert_eq!(account.available, Decimal::ZERO);
-        assert_eq!(account.total, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).total, Decimal::ZERO);
     }
 
     #[test]
     fn test_deposit_negative_amount_slightly_less_than_balance() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
-        let result = deposit(&mut account, Decimal::from_str("-99.99").unwrap());
+        let result = deposit(
+            &mut account,
+            Currency::USD,
+            Decimal::from_str("-99.99").unwrap(),
+        );
 
         assert!(result.is_ok()); // Should still be positive
-        assert_eq!(account.available, Decimal::from_str("0.01").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0.01").unwrap());
+        assert_eq!(
+            account.balance(Currency::USD).available,
+            Decimal::from_str("0.01").unwrap()
+        );
+        assert_eq!(
+            account.balance(Currency::USD).total,
+            Decimal::from_str("0.01").unwrap()
+        );
     }
 
     #[test]
     fn test_deposit_negative_amount_slightly_more_than_balance() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
-        let result = deposit(&mut account, Decimal::from_str("-100.01").unwrap());
+        let result = deposit(
+            &mut account,
+            Currency::USD,
+            Decimal::from_str("-100.01").unwrap(),
+        );
 
         assert!(result.is_err()); // Should be negative
         match result {
-            Err(EngineError::InvalidTransaction { message }) => {
-                assert_eq!(message, "Total balance is negative");
+            Err(EngineError::NegativeBalance(client)) => {
+                assert_eq!(client, 1);
             }
-            _ => panic!("Expected InvalidTransaction error"),
+            _ => panic!("Expected NegativeBalance error"),
         }
     }
 }
@@ -202,73 +222,73 @@ mod withdraw_tests {
     #[test]
     fn test_withdraw_basic_functionality() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
-        let result = withdraw(&mut account, Decimal::from(50));
+        let result = withdraw(&mut account, Currency::USD, Decimal::from(50));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(50));
-        assert_eq!(account.total, Decimal::from(50));
-        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
     }
 
     #[test]
     fn test_withdraw_insufficient_funds() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(50);
-        account.total = Decimal::from(50);
+        account.balance_mut(Currency::USD).available = Decimal::from(50);
+        account.balance_mut(Currency::USD).total = Decimal::from(50);
 
-        let result = withdraw(&mut account, Decimal::from(100));
+        let result = withdraw(&mut account, Currency::USD, Decimal::from(100));
 
         assert!(result.is_err());
         match result {
-            Err(EngineError::InvalidTransaction { message }) => {
-                assert_eq!(message, "Insufficient funds");
+            Err(EngineError::InsufficientFunds(client)) => {
+                assert_eq!(client, 1);
             }
-            _ => panic!("Expected InvalidTransaction error"),
+            _ => panic!("Expected InsufficientFunds error"),
         }
     }
 
     #[test]
     fn test_withdraw_exact_amount() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
-        let result = withdraw(&mut account, Decimal::from(100));
+        let result = withdraw(&mut account, Currency::USD, Decimal::from(100));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::ZERO);
-        assert_eq!(account.total, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).total, Decimal::ZERO);
     }
 
     #[test]
     fn test_withdraw_zero_amount() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
-        let result = withdraw(&mut account, Decimal::ZERO);
+        let result = withdraw(&mut account, Currency::USD, Decimal::ZERO);
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(100));
-        assert_eq!(account.total, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
     }
 
     #[test]
     fn test_withdraw_preserves_held_amount() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.held = Decimal::from(50);
-        account.total = Decimal::from(150);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).held = Decimal::from(50);
+        account.balance_mut(Currency::USD).total = Decimal::from(150);
 
-        let result = withdraw(&mut account, Decimal::from(25));
+        let result = withdraw(&mut account, Currency::USD, Decimal::from(25));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(75));
-        assert_eq!(account.held, Decimal::from(50));
-        assert_eq!(account.total, Decimal::from(125));
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(75));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(125));
     }
 }
 
@@ -279,51 +299,51 @@ mod dispute_tests {
     #[test]
     fn test_dispute_basic_functionality() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
         let tx = Transaction::new_deposit(1, 1, Decimal::from(50));
 
-        let result = dispute(&mut account, &tx);
+        let result = dispute(&mut account, &tx, DisputeHold::Partial);
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(50));
-        assert_eq!(account.held, Decimal::from(50));
-        assert_eq!(account.total, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
     }
 
     #[test]
     fn test_dispute_insufficient_funds() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(25);
-        account.total = Decimal::from(25);
+        account.balance_mut(Currency::USD).available = Decimal::from(25);
+        account.balance_mut(Currency::USD).total = Decimal::from(25);
 
         let tx = Transaction::new_deposit(1, 1, Decimal::from(50));
 
-        let result = dispute(&mut account, &tx);
+        let result = dispute(&mut account, &tx, DisputeHold::Partial);
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::ZERO);
-        assert_eq!(account.held, Decimal::from(25));
-        assert_eq!(account.total, Decimal::from(25));
+        assert_eq!(account.balance(Currency::USD).available, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).held, Decimal::from(25));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(25));
     }
 
     #[test]
     fn test_dispute_transaction_without_amount() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(100);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(100);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
         let tx = Transaction::new_dispute(1, 1);
 
-        let result = dispute(&mut account, &tx);
+        let result = dispute(&mut account, &tx, DisputeHold::Partial);
 
         assert!(result.is_err());
         match result {
-            Err(EngineError::ZeroAmount(tx_id)) => {
+            Err(EngineError::MissingAmount(tx_id)) => {
                 assert_eq!(tx_id, 1);
             }
-            _ => panic!("Expected ZeroAmount error"),
+            _ => panic!("Expected MissingAmount error"),
         }
     }
 }
@@ -335,38 +355,36 @@ mod resolve_tests {
     #[test]
     fn test_resolve_basic_functionality() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(50);
-        account.held = Decimal::from(50);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(50);
+        account.balance_mut(Currency::USD).held = Decimal::from(50);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
         let tx = Transaction::new_deposit(1, 1, Decimal::from(50));
 
-        let result = resolve(&mut account, &tx);
+        let result = resolve(&mut account, &tx, Decimal::from(50));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(100));
-        assert_eq!(account.held, Decimal::ZERO);
-        assert_eq!(account.total, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
     }
 
     #[test]
-    fn test_resolve_transaction_without_amount() {
+    fn test_resolve_releases_only_what_was_held() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(50);
-        account.held = Decimal::from(50);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(80);
+        account.balance_mut(Currency::USD).held = Decimal::from(20);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
-        let tx = Transaction::new_resolve(1, 1);
+        // A partial dispute held 20 of the 50 deposited
+        let tx = Transaction::new_deposit(1, 1, Decimal::from(50));
 
-        let result = resolve(&mut account, &tx);
+        let result = resolve(&mut account, &tx, Decimal::from(20));
 
-        assert!(result.is_err());
-        match result {
-            Err(EngineError::ZeroAmount(tx_id)) => {
-                assert_eq!(tx_id, 1);
-            }
-            _ => panic!("Expected ZeroAmount error"),
-        }
+        assert!(result.is_ok());
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(100));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(100));
     }
 }
 
@@ -377,52 +395,51 @@ mod chargeback_tests {
     #[test]
     fn test_chargeback_basic_functionality() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(50);
-        account.held = Decimal::from(50);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(50);
+        account.balance_mut(Currency::USD).held = Decimal::from(50);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
         let tx = Transaction::new_deposit(1, 1, Decimal::from(50));
 
-        let result = chargeback(&mut account, &tx);
+        let result = chargeback(&mut account, &tx, Decimal::from(50));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, Decimal::from(50));
-        assert_eq!(account.held, Decimal::ZERO);
-        assert_eq!(account.total, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::ZERO);
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(50));
         assert!(account.locked);
     }
 
     #[test]
-    fn test_chargeback_transaction_without_amount() {
+    fn test_chargeback_releases_only_what_was_held() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(50);
-        account.held = Decimal::from(50);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(50);
+        account.balance_mut(Currency::USD).held = Decimal::from(50);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
 
-        let tx = Transaction::new_chargeback(1, 1);
+        // Another dispute holds the other 30
+        let tx = Transaction::new_deposit(1, 1, Decimal::from(50));
 
-        let result = chargeback(&mut account, &tx);
+        let result = chargeback(&mut account, &tx, Decimal::from(20));
 
-        assert!(result.is_err());
-        match result {
-            Err(EngineError::ZeroAmount(tx_id)) => {
-                assert_eq!(tx_id, 1);
-            }
-            _ => panic!("Expected ZeroAmount error"),
-        }
+        assert!(result.is_ok());
+        assert_eq!(account.balance(Currency::USD).available, Decimal::from(50));
+        assert_eq!(account.balance(Currency::USD).held, Decimal::from(30));
+        assert_eq!(account.balance(Currency::USD).total, Decimal::from(80));
+        assert!(account.locked);
     }
 
     #[test]
     fn test_chargeback_locks_account() {
         let mut account = Account::new(1);
-        account.available = Decimal::from(50);
-        account.held = Decimal::from(50);
-        account.total = Decimal::from(100);
+        account.balance_mut(Currency::USD).available = Decimal::from(50);
+        account.balance_mut(Currency::USD).held = Decimal::from(50);
+        account.balance_mut(Currency::USD).total = Decimal::from(100);
         account.locked = false;
 
         let tx = Transaction::new_deposit(1, 1, Decimal::from(50));
 
-        let result = chargeback(&mut account, &tx);
+        let result = chargeback(&mut account, &tx, Decimal::from(50));
 
         assert!(result.is_ok());
         assert!(account.locked);
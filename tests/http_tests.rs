@@ -0,0 +1,338 @@
+use octopi::engine::Engine;
+use octopi::handle::EngineHandle;
+use octopi::http::{router, AdminTokens};
+use octopi::policy::IngestPolicy;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use http_body_util::BodyExt;
+use serde_json::Value;
+use tower::ServiceExt;
+
+fn app() -> Router {
+    router(
+        EngineHandle::spawn(Engine::default(), 16),
+        IngestPolicy::default(),
+        AdminTokens::default(),
+    )
+}
+
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn post(app: &Router, body: &str) -> (StatusCode, Value) {
+    let request = Request::post("/transactions")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    send(app, request).await
+}
+
+async fn get(app: &Router, uri: &str) -> (StatusCode, Value) {
+    send(app, Request::get(uri).body(Body::empty()).unwrap()).await
+}
+
+#[tokio::test]
+async fn test_http_submit_and_query() {
+    let app = app();
+
+    let (status, body) = post(
+        &app,
+        r#"{"type":"deposit","client":1,"tx":1,"amount":"100.0"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["tx"], 1);
+
+    post(&app, r#"{"type":"dispute","client":1,"tx":1}"#).await;
+
+    let (status, body) = get(&app, "/accounts/1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["client"], 1);
+    assert_eq!(body[0]["currency"], "USD");
+    assert_eq!(body[0]["available"], "0.0");
+    assert_eq!(body[0]["held"], "100.0");
+
+    let (status, body) = get(&app, "/transactions/1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["type"], "deposit");
+    assert_eq!(body["client"], 1);
+    assert_eq!(body["amount"], "100.0");
+    assert_eq!(body["state"], "disputed");
+}
+
+#[tokio::test]
+async fn test_http_client_history() {
+    let app = app();
+
+    post(
+        &app,
+        r#"[
+            {"type":"deposit","client":1,"tx":1,"amount":"100"},
+            {"type":"deposit","client":2,"tx":2,"amount":"5"},
+            {"type":"transfer","client":2,"tx":3,"amount":"5","to":1},
+            {"type":"withdrawal","client":1,"tx":4,"amount":"10"},
+            {"type":"dispute","client":1,"tx":1}
+        ]"#,
+    )
+    .await;
+
+    let (status, body) = get(&app, "/accounts/1/transactions").await;
+    assert_eq!(status, StatusCode::OK);
+    let tx_ids: Vec<&Value> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tx| &tx["tx"])
+        .collect();
+    assert_eq!(tx_ids, [1, 3, 4]);
+    assert_eq!(body[0]["state"], "disputed");
+    assert_eq!(body[1]["type"], "transfer");
+    assert_eq!(body[1]["to"], 1);
+    assert_eq!(body[1]["currency"], "USD");
+
+    let (status, body) = get(&app, "/accounts/1/disputes").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["tx"], 1);
+
+    let (status, body) = get(&app, "/accounts/2/disputes").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, Value::Array(Vec::new()));
+
+    let (status, body) = get(&app, "/accounts/7/transactions").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "unknown_client");
+}
+
+#[tokio::test]
+async fn test_http_batch_reports_each_transaction() {
+    let app = app();
+
+    let (status, body) = post(
+        &app,
+        r#"[
+            {"type":"deposit","client":2,"tx":1,"amount":"5"},
+            {"type":"deposit","client":1,"tx":2,"amount":"10"},
+            {"type":"deposit","client":1,"tx":2,"amount":"10"},
+            {"type":"withdrawal","client":1,"tx":3,"amount":"50"},
+            {"type":"deposit","client":1}
+        ]"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let results = body.as_array().unwrap();
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0]["status"], "ok");
+    assert_eq!(results[1]["status"], "ok");
+    assert_eq!(results[2]["code"], "duplicate_transaction");
+    assert_eq!(results[3]["code"], "insufficient_funds");
+    assert_eq!(results[4]["code"], "malformed_record");
+
+    let (status, body) = get(&app, "/accounts").await;
+    assert_eq!(status, StatusCode::OK);
+    let clients: Vec<&Value> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|a| &a["client"])
+        .collect();
+    assert_eq!(clients, [1, 2]);
+}
+
+#[tokio::test]
+async fn test_http_error_statuses() {
+    let app = app();
+
+    post(
+        &app,
+        r#"{"type":"deposit","client":1,"tx":1,"amount":"10"}"#,
+    )
+    .await;
+
+    let (status, body) = post(
+        &app,
+        r#"{"type":"deposit","client":1,"tx":1,"amount":"10"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["status"], "error");
+    assert_eq!(body["code"], "duplicate_transaction");
+
+    let (status, body) = post(&app, r#"{"type":"dispute","client":1,"tx":9}"#).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "unknown_transaction");
+
+    let (status, body) = post(&app, r#"{"type":"dispute","client":2,"tx":1}"#).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "invalid_client");
+
+    let (status, body) = post(
+        &app,
+        r#"{"type":"deposit","client":1,"tx":2,"amount":"1.00001"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "precision_exceeded");
+
+    let (status, body) = post(
+        &app,
+        r#"{"type":"dispute","client":1,"tx":1,"amount":"10"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "unexpected_amount");
+
+    let (status, body) = post(&app, "not json").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "malformed_record");
+
+    let (status, body) = get(&app, "/accounts/7").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "unknown_client");
+
+    let (status, body) = get(&app, "/transactions/7").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "unknown_transaction");
+}
+
+fn admin_request(token: Option<&str>, body: &str) -> Request<Body> {
+    let mut request = Request::post("/admin").header("content-type", "application/json");
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+#[tokio::test]
+async fn test_http_admin_actions() {
+    let handle = EngineHandle::spawn(Engine::default(), 16);
+    let tokens = "# operators\njo:s3cret\n".parse().unwrap();
+    let app = router(handle.clone(), IngestPolicy::default(), tokens);
+
+    post(
+        &app,
+        r#"[{"type":"deposit","client":1,"tx":1,"amount":"5"},{"type":"dispute","client":1,"tx":1},{"type":"chargeback","client":1,"tx":1}]"#,
+    )
+    .await;
+
+    let admin = |body: &str| admin_request(Some("s3cret"), body);
+
+    let (status, body) = send(
+        &app,
+        admin(r#"{"client":1,"action":"unlock","reason":"bank error"}"#),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["locked"], false);
+
+    let (status, body) = send(
+        &app,
+        admin(r#"{"client":1,"action":"unlock","reason":"again"}"#),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "account_not_locked");
+
+    let (status, body) = send(&app, admin(r#"{"client":1,"action":"unlock"}"#)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "malformed_record");
+
+    let (status, _) = send(
+        &app,
+        admin(r#"{"client":1,"action":"close","reason":"client left"}"#),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = post(&app, r#"{"type":"deposit","client":1,"tx":2,"amount":"5"}"#).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "account_closed");
+
+    // Actions are recorded under the operator the token belongs to
+    let engine = handle.shutdown().await.unwrap();
+    let log = engine.admin_log(1);
+    assert_eq!(log.len(), 2);
+    assert!(log.iter().all(|event| event.operator == "jo"));
+}
+
+#[tokio::test]
+async fn test_http_admin_needs_a_known_token() {
+    let handle = EngineHandle::spawn(Engine::default(), 16);
+    let tokens = "jo:s3cret".parse().unwrap();
+    let app = router(handle.clone(), IngestPolicy::default(), tokens);
+    post(&app, r#"{"type":"deposit","client":1,"tx":1,"amount":"5"}"#).await;
+
+    let freeze = r#"{"client":1,"action":"freeze","reason":"suspected fraud"}"#;
+    for token in [None, Some("guess"), Some("s3cre")] {
+        let response = app
+            .clone()
+            .oneshot(admin_request(token, freeze))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+    }
+
+    // The operator comes from the token, not from the body
+    let (status, body) = send(
+        &app,
+        admin_request(
+            Some("s3cret"),
+            r#"{"client":1,"action":"freeze","operator":"root","reason":"suspected fraud"}"#,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "malformed_record");
+
+    // Without any token configured nobody can take an admin action
+    let unconfigured = router(
+        handle.clone(),
+        IngestPolicy::default(),
+        AdminTokens::default(),
+    );
+    let (status, body) = send(&unconfigured, admin_request(Some(""), freeze)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+
+    let engine = handle.shutdown().await.unwrap();
+    assert!(!engine.account(1).unwrap().locked);
+    assert!(engine.admin_log(1).is_empty());
+}
+
+#[test]
+fn test_admin_tokens_parse() {
+    let tokens: AdminTokens = "jo:abc\n\n# comment\nsam:a:b\n".parse().unwrap();
+    assert_eq!(tokens.operator("abc"), Some("jo"));
+    assert_eq!(tokens.operator("a:b"), Some("sam"));
+    assert_eq!(tokens.operator("ab"), None);
+
+    assert!("jo".parse::<AdminTokens>().is_err());
+    assert!("jo:".parse::<AdminTokens>().is_err());
+    assert!("jo:abc\nsam:abc".parse::<AdminTokens>().is_err());
+}
+
+#[tokio::test]
+async fn test_http_engine_stopped() {
+    let handle = EngineHandle::spawn(Engine::default(), 16);
+    let app = router(
+        handle.clone(),
+        IngestPolicy::default(),
+        AdminTokens::default(),
+    );
+    handle.shutdown().await.unwrap();
+
+    let (status, body) = get(&app, "/accounts").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["code"], "engine_stopped");
+}
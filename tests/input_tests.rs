@@ -0,0 +1,435 @@
+use octopi::currency::Currency;
+use octopi::error::EngineError;
+use octopi::input::{decompress, has_input_extension, Compression, InputFormat};
+use octopi::policy::{ExcessPrecision, IngestPolicy};
+use octopi::transaction::Transaction;
+use octopi::{stream_records, stream_records_from, Record};
+
+use flate2::write::GzEncoder;
+use rust_decimal::Decimal;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use tempfile::tempdir;
+
+const CSV_CONTENT: &str = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n";
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn read_all(data: &[u8]) -> String {
+    let mut out = String::new();
+    decompress(data).unwrap().read_to_string(&mut out).unwrap();
+    out
+}
+
+/// Hands out a single byte per read, like a slow pipe.
+struct ByteAtATime<'a>(&'a [u8]);
+
+impl Read for ByteAtATime<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() || buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = self.0[0];
+        self.0 = &self.0[1..];
+        Ok(1)
+    }
+}
+
+#[test]
+fn test_sniff_compression() {
+    assert_eq!(Compression::sniff(&[0x1f, 0x8b, 0x08]), Compression::Gzip);
+    assert_eq!(
+        Compression::sniff(&[0x28, 0xb5, 0x2f, 0xfd]),
+        Compression::Zstd
+    );
+    assert_eq!(Compression::sniff(b"type"), Compression::None);
+    assert_eq!(Compression::sniff(&[0x28, 0xb5]), Compression::None);
+    assert_eq!(Compression::sniff(&[]), Compression::None);
+}
+
+#[test]
+fn test_decompress_passes_plain_input_through() {
+    assert_eq!(read_all(CSV_CONTENT.as_bytes()), CSV_CONTENT);
+    assert_eq!(read_all(b"ab"), "ab");
+    assert_eq!(read_all(b""), "");
+}
+
+#[test]
+fn test_decompress_gzip_and_zstd() {
+    assert_eq!(read_all(&gzip(CSV_CONTENT.as_bytes())), CSV_CONTENT);
+
+    let zstd = zstd::encode_all(CSV_CONTENT.as_bytes(), 0).unwrap();
+    assert_eq!(read_all(&zstd), CSV_CONTENT);
+}
+
+#[test]
+fn test_decompress_concatenated_gzip_members() {
+    let (first, second) = CSV_CONTENT.split_at(30);
+    let mut data = gzip(first.as_bytes());
+    data.extend(gzip(second.as_bytes()));
+
+    assert_eq!(read_all(&data), CSV_CONTENT);
+}
+
+#[test]
+fn test_decompress_short_reads() {
+    let zstd = zstd::encode_all(CSV_CONTENT.as_bytes(), 0).unwrap();
+
+    let mut out = String::new();
+    decompress(ByteAtATime(&zstd))
+        .unwrap()
+        .read_to_string(&mut out)
+        .unwrap();
+
+    assert_eq!(out, CSV_CONTENT);
+}
+
+#[test]
+fn test_stream_records_from_compressed_files() {
+    let dir = tempdir().unwrap();
+    let gz_path = dir.path().join("day1.csv.gz");
+    let zst_path = dir.path().join("day2.csv.zst");
+    fs::write(&gz_path, gzip(CSV_CONTENT.as_bytes())).unwrap();
+    fs::write(
+        &zst_path,
+        zstd::encode_all(CSV_CONTENT.as_bytes(), 0).unwrap(),
+    )
+    .unwrap();
+
+    for path in [gz_path, zst_path] {
+        let records: Vec<_> = stream_records(path.to_str().unwrap()).unwrap().collect();
+
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.result.is_ok()));
+        assert_eq!(records[1].origin.raw, "deposit,1,2,2.0");
+    }
+}
+
+#[test]
+fn test_has_input_extension() {
+    assert!(has_input_extension("day1.csv"));
+    assert!(has_input_extension("day1.ndjson.gz"));
+    assert!(has_input_extension("day1.CSV.GZ"));
+    assert!(has_input_extension("archive/day1.csv.zst"));
+    assert!(!has_input_extension("day1.gz"));
+    assert!(!has_input_extension("day1.txt.zst"));
+    assert!(!has_input_extension("day1.txt"));
+}
+
+fn read_as(data: &str, format: InputFormat) -> Vec<Record> {
+    stream_records_from(data.as_bytes(), "-", format)
+        .unwrap()
+        .collect()
+}
+
+#[test]
+fn test_input_format_from_path() {
+    assert_eq!(InputFormat::from_path("day1.csv"), Some(InputFormat::Csv));
+    assert_eq!(
+        InputFormat::from_path("day1.NDJSON"),
+        Some(InputFormat::Ndjson)
+    );
+    assert_eq!(
+        InputFormat::from_path("day1.jsonl.zst"),
+        Some(InputFormat::Ndjson)
+    );
+    assert_eq!(
+        InputFormat::from_path("export/day1.json.gz"),
+        Some(InputFormat::JsonArray)
+    );
+    assert_eq!(InputFormat::from_path("day1.gz"), None);
+    assert_eq!(InputFormat::from_path("-"), None);
+    assert_eq!("json".parse(), Ok(InputFormat::JsonArray));
+    assert!("xml".parse::<InputFormat>().is_err());
+}
+
+#[test]
+fn test_ndjson_input() {
+    let records = read_as(
+        r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}
+
+{"type":"dispute","client":1,"tx":1}
+{"type":"deposit","client":1
+{"type":"withdrawal","client":1,"tx":2,"amount":1}
+"#,
+        InputFormat::Ndjson,
+    );
+
+    assert_eq!(records.len(), 4);
+
+    let deposit = records[0].result.as_ref().unwrap();
+    assert_eq!(deposit.tx, 1);
+    assert_eq!(deposit.amount, Some(Decimal::from_str("1.5").unwrap()));
+    assert_eq!(records[0].origin.row, 1);
+
+    assert_eq!(records[1].result.as_ref().unwrap().amount, None);
+    assert_eq!(records[1].origin.row, 3);
+
+    assert!(records[2].result.is_err());
+    assert_eq!(records[2].origin.raw, r#"{"type":"deposit","client":1"#);
+
+    let withdrawal = records[3].result.as_ref().unwrap();
+    assert_eq!(withdrawal.amount, Some(Decimal::ONE));
+}
+
+#[test]
+fn test_currency_column() {
+    let records = read_as(
+        "type,client,tx,amount,currency\n\
+         deposit,1,1,1.0,eur\n\
+         deposit,1,2,1.0,\n\
+         deposit,1,3,1.0,EURO\n",
+        InputFormat::Csv,
+    );
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(
+        records[0].result.as_ref().unwrap().currency,
+        Some(Currency::EUR)
+    );
+    assert_eq!(records[1].result.as_ref().unwrap().currency, None);
+    assert!(records[2].result.is_err());
+
+    let records = read_as(
+        r#"{"type":"deposit","client":1,"tx":1,"amount":"1","currency":"GBP"}"#,
+        InputFormat::Ndjson,
+    );
+    assert_eq!(
+        records[0].result.as_ref().unwrap().currency,
+        Some(Currency::GBP)
+    );
+
+    let tx = Transaction::try_from(
+        read_as(CSV_CONTENT, InputFormat::Csv)
+            .remove(0)
+            .result
+            .unwrap(),
+    );
+    assert_eq!(tx.unwrap().currency, Currency::USD);
+}
+
+#[test]
+fn test_transfer_rows() {
+    let records = read_as(
+        "type,client,tx,amount,to\n\
+         transfer,1,1,2.5,2\n\
+         transfer,1,2,2.5,\n",
+        InputFormat::Csv,
+    );
+
+    assert_eq!(records.len(), 2);
+    let mut records = records.into_iter().map(|record| record.result.unwrap());
+
+    let transfer = Transaction::try_from(records.next().unwrap()).unwrap();
+    assert_eq!(
+        transfer,
+        Transaction::new_transfer(1, 2, 1, Decimal::from_str("2.5").unwrap())
+    );
+
+    // A transfer needs somewhere to go
+    assert!(Transaction::try_from(records.next().unwrap()).is_err());
+}
+
+#[test]
+fn test_timestamp_column() {
+    let records = read_as(
+        "type,client,tx,amount,timestamp\n\
+         deposit,1,1,2.5,1700000000\n\
+         deposit,1,2,2.5,\n\
+         deposit,1,3,2.5,yesterday\n",
+        InputFormat::Csv,
+    );
+
+    assert_eq!(records.len(), 3);
+    assert!(records[2].result.is_err());
+    let mut records = records
+        .into_iter()
+        .take(2)
+        .map(|record| Transaction::try_from(record.result.unwrap()).unwrap());
+
+    assert_eq!(
+        records.next().unwrap(),
+        Transaction::new_deposit(1, 1, Decimal::from_str("2.5").unwrap())
+            .with_timestamp(1700000000)
+    );
+    assert_eq!(records.next().unwrap().timestamp, None);
+
+    let records = read_as(
+        r#"{"type":"dispute","client":1,"tx":1,"timestamp":1700000001}"#,
+        InputFormat::Ndjson,
+    );
+    assert_eq!(
+        records[0].result.as_ref().unwrap().timestamp,
+        Some(1700000001)
+    );
+}
+
+#[test]
+fn test_excess_precision() {
+    let amount = |policy: &IngestPolicy, amount: &str| {
+        let record = read_as(
+            &format!("type,client,tx,amount\nwithdrawal,1,7,{}\n", amount),
+            InputFormat::Csv,
+        )
+        .remove(0)
+        .result
+        .unwrap();
+        record.into_transaction(policy).map(|tx| tx.amount.unwrap())
+    };
+    let dec = |s: &str| Decimal::from_str(s).unwrap();
+
+    // Trailing zeros do not count against the scale
+    let policy = IngestPolicy::default();
+    assert_eq!(amount(&policy, "1.250000").unwrap(), dec("1.25"));
+    assert!(matches!(
+        amount(&policy, "1.00005"),
+        Err(EngineError::PrecisionExceeded(7, 4))
+    ));
+
+    let rounded = |excess_precision: ExcessPrecision, value: &str| {
+        let policy = IngestPolicy {
+            max_scale: 2,
+            excess_precision,
+            ..IngestPolicy::default()
+        };
+        amount(&policy, value).unwrap()
+    };
+
+    assert_eq!(rounded(ExcessPrecision::Bankers, "1.125"), dec("1.12"));
+    assert_eq!(rounded(ExcessPrecision::Bankers, "1.135"), dec("1.14"));
+    assert_eq!(rounded(ExcessPrecision::Truncate, "1.129"), dec("1.12"));
+    assert_eq!(rounded(ExcessPrecision::HalfUp, "1.125"), dec("1.13"));
+    assert_eq!(rounded(ExcessPrecision::HalfUp, "1.124"), dec("1.12"));
+}
+
+#[test]
+fn test_amount_validation() {
+    let convert = |policy: &IngestPolicy, row: &str| {
+        read_as(
+            &format!("type,client,tx,amount\n{}\n", row),
+            InputFormat::Csv,
+        )
+        .remove(0)
+        .result
+        .unwrap()
+        .into_transaction(policy)
+    };
+
+    let strict = IngestPolicy::default();
+    for row in ["deposit,1,1,0", "deposit,1,1,-5", "withdrawal,1,1,-0.5"] {
+        assert!(matches!(
+            convert(&strict, row),
+            Err(EngineError::NonPositiveAmount(1))
+        ));
+    }
+    for row in ["dispute,1,1,0", "resolve,1,1,1.0", "chargeback,1,1,-1"] {
+        assert!(matches!(
+            convert(&strict, row),
+            Err(EngineError::UnexpectedAmount(1))
+        ));
+    }
+    assert!(convert(&strict, "dispute,1,1,").is_ok());
+
+    // Truncated to nothing is no better than zero to start with
+    let truncating = IngestPolicy {
+        excess_precision: ExcessPrecision::Truncate,
+        ..IngestPolicy::default()
+    };
+    assert!(matches!(
+        convert(&truncating, "deposit,1,1,0.00001"),
+        Err(EngineError::NonPositiveAmount(1))
+    ));
+
+    let lenient = IngestPolicy {
+        reject_non_positive: false,
+        reject_reference_amounts: false,
+        ..IngestPolicy::default()
+    };
+    assert_eq!(
+        convert(&lenient, "deposit,1,1,-5").unwrap().amount,
+        Some(Decimal::from(-5))
+    );
+    assert_eq!(
+        convert(&lenient, "dispute,1,1,1.00001").unwrap().amount,
+        Some(Decimal::from_str("1.00001").unwrap())
+    );
+}
+
+#[test]
+fn test_json_array_input() {
+    let records = read_as(
+        r#" [
+            {"type":"deposit","client":1,"tx":1,"amount":"1.5"},
+            {"type":"deposit","client":"one","tx":2,"amount":"1"} ,
+            {"type":"dispute","client":1,"tx":1}
+        ] "#,
+        InputFormat::JsonArray,
+    );
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].result.as_ref().unwrap().tx, 1);
+    assert!(records[1].result.is_err());
+    assert_eq!(records[1].origin.row, 2);
+    assert!(records[1].origin.raw.contains(r#""client":"one""#));
+    assert_eq!(records[2].result.as_ref().unwrap().tx, 1);
+    assert_eq!(records[2].origin.row, 3);
+
+    assert!(read_as("[]", InputFormat::JsonArray).is_empty());
+    assert!(read_as(" [ ] ", InputFormat::JsonArray).is_empty());
+}
+
+#[test]
+fn test_json_array_input_stops_at_syntax_errors() {
+    let records = read_as(
+        r#"[{"type":"deposit","client":1,"tx":1,"amount":"1"} {"type":"deposit"}]"#,
+        InputFormat::JsonArray,
+    );
+    assert_eq!(records.len(), 2);
+    assert!(records[0].result.is_ok());
+    assert!(records[1].result.is_err());
+
+    let records = read_as(r#"{"type":"deposit"}"#, InputFormat::JsonArray);
+    assert_eq!(records.len(), 1);
+    assert!(records[0].result.is_err());
+
+    let records = read_as(r#"[1, {"type":"deposit"}]"#, InputFormat::JsonArray);
+    assert_eq!(records.len(), 1);
+    assert!(records[0].result.is_err());
+
+    let records = read_as(
+        r#"[{"type":"deposit","client":1,"tx":1"#,
+        InputFormat::JsonArray,
+    );
+    assert_eq!(records.len(), 1);
+    assert!(records[0].result.is_err());
+}
+
+#[test]
+fn test_formats_produce_the_same_transactions() {
+    let csv = "type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\ndispute,1,1,\n";
+    let ndjson = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}
+{"type":"withdrawal","client":1,"tx":2,"amount":"0.5"}
+{"type":"dispute","client":1,"tx":1}
+"#;
+    let json = format!("[{}]", ndjson.trim().replace('\n', ","));
+
+    let transactions = |records: Vec<Record>| -> Vec<Transaction> {
+        records
+            .into_iter()
+            .map(|record| record.result.unwrap().try_into().unwrap())
+            .collect()
+    };
+
+    let from_csv = transactions(read_as(csv, InputFormat::Csv));
+    assert_eq!(from_csv.len(), 3);
+    assert_eq!(transactions(read_as(ndjson, InputFormat::Ndjson)), from_csv);
+    assert_eq!(
+        transactions(read_as(&json, InputFormat::JsonArray)),
+        from_csv
+    );
+}
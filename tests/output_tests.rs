@@ -0,0 +1,124 @@
+use octopi::currency::Currency;
+use octopi::engine::Engine;
+use octopi::output::{account_writer, OutputFormat};
+use octopi::stream_transactions;
+use octopi::transaction::Transaction;
+
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+fn engine_from_file(path: &str) -> Engine {
+    let mut engine = Engine::default();
+    for csv_tx in stream_transactions(path).unwrap().flatten() {
+        if let Ok(tx) = Transaction::try_from(csv_tx) {
+            let _ = engine.apply_transaction(tx);
+        }
+    }
+    engine
+}
+
+fn render(engine: &Engine, format: OutputFormat) -> String {
+    let mut buf = Vec::new();
+    {
+        let mut writer = account_writer(&mut buf, format);
+        engine.write_accounts(writer.as_mut()).unwrap();
+    }
+    String::from_utf8(buf).unwrap()
+}
+
+#[test]
+fn test_output_format_from_str() {
+    assert_eq!(OutputFormat::from_str("csv"), Ok(OutputFormat::Csv));
+    assert_eq!(OutputFormat::from_str("JSON"), Ok(OutputFormat::Json));
+    assert_eq!(OutputFormat::from_str("ndjson"), Ok(OutputFormat::Ndjson));
+    assert!(OutputFormat::from_str("xml").is_err());
+}
+
+#[test]
+fn test_csv_output_is_deterministic() {
+    let path = "integration_test_data/medium.csv";
+
+    let expected = render(&engine_from_file(path), OutputFormat::Csv);
+    for _ in 0..10 {
+        assert_eq!(render(&engine_from_file(path), OutputFormat::Csv), expected);
+    }
+
+    let clients: Vec<u16> = expected
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    let mut sorted = clients.clone();
+    sorted.sort_unstable();
+    assert_eq!(clients, sorted);
+}
+
+#[test]
+fn test_json_output() {
+    let mut engine = Engine::default();
+    let tx = Transaction::new_deposit(2, 1, Decimal::from_str("1.23456").unwrap());
+    engine.apply_transaction(tx).unwrap();
+    let tx = Transaction::new_deposit(1, 2, Decimal::from(10));
+    engine.apply_transaction(tx).unwrap();
+
+    let output = render(&engine, OutputFormat::Json);
+    let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+    let accounts = value.as_array().unwrap();
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[0]["client"], 1);
+    assert_eq!(accounts[0]["available"], "10");
+    assert_eq!(accounts[1]["client"], 2);
+    assert_eq!(accounts[1]["available"], "1.2346");
+    assert_eq!(accounts[1]["held"], "0");
+    assert_eq!(accounts[1]["locked"], false);
+}
+
+#[test]
+fn test_json_output_empty() {
+    let engine = Engine::default();
+
+    assert_eq!(render(&engine, OutputFormat::Json), "[]\n");
+}
+
+#[test]
+fn test_ndjson_output() {
+    let mut engine = Engine::default();
+    for client in [3, 1, 2] {
+        let tx = Transaction::new_deposit(client, client as u32, Decimal::from(5));
+        engine.apply_transaction(tx).unwrap();
+    }
+
+    let output = render(&engine, OutputFormat::Ndjson);
+    let clients: Vec<u64> = output
+        .lines()
+        .map(|line| {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            value["client"].as_u64().unwrap()
+        })
+        .collect();
+
+    assert_eq!(clients, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_csv_output_one_row_per_currency() {
+    let mut engine = Engine::default();
+    for tx in [
+        Transaction::new_deposit(2, 1, Decimal::from(5)),
+        Transaction::new_deposit(1, 2, Decimal::from(10)).with_currency(Currency::GBP),
+        Transaction::new_deposit(1, 3, Decimal::from(20)),
+        Transaction::new_deposit(1, 4, Decimal::from(30)).with_currency(Currency::EUR),
+    ] {
+        engine.apply_transaction(tx).unwrap();
+    }
+
+    assert_eq!(
+        render(&engine, OutputFormat::Csv),
+        "client,currency,available,held,total,locked\n\
+         1,EUR,30,0,30,false\n\
+         1,GBP,10,0,10,false\n\
+         1,USD,20,0,20,false\n\
+         2,USD,5,0,5,false\n"
+    );
+}
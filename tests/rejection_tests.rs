@@ -0,0 +1,187 @@
+use octopi::error::{EngineError, ErrorCategory, RecordError};
+use octopi::rejection::{Rejection, RejectionFormat, RejectionWriter, MALFORMED_RECORD};
+use octopi::{stream_records, Origin};
+
+use std::fs;
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_stream_records_keeps_row_and_raw() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let csv_content = r#"type,client,tx,amount
+deposit, 1, 1, 100.00
+hello,1,2,50.00
+withdrawal,1,3,25.00"#;
+
+    fs::write(&temp_file, csv_content).unwrap();
+
+    let records: Vec<_> = stream_records(temp_file.path().to_str().unwrap())
+        .unwrap()
+        .collect();
+
+    assert_eq!(records.len(), 3);
+
+    assert_eq!(records[0].origin.row, 2);
+    assert_eq!(records[0].origin.raw, "deposit,1,1,100.00");
+    assert!(records[0].result.is_ok());
+
+    assert_eq!(records[1].origin.row, 3);
+    assert_eq!(records[1].origin.raw, "hello,1,2,50.00");
+    assert!(records[1].result.is_err());
+
+    assert_eq!(records[2].origin.row, 4);
+    assert!(records[2].result.is_ok());
+}
+
+#[test]
+fn test_rejection_from_engine_error() {
+    let origin = Origin {
+        source: "day1.csv".to_string(),
+        row: 7,
+        offset: 120,
+        raw: "dispute,1,42,".to_string(),
+    };
+
+    let rejection = Rejection::engine(origin, 1, 42, &EngineError::NonExistentTransaction(42));
+
+    assert_eq!(rejection.source, "day1.csv");
+    assert_eq!(rejection.row, 7);
+    assert_eq!(rejection.raw, "dispute,1,42,");
+    assert_eq!(rejection.client, Some(1));
+    assert_eq!(rejection.tx, Some(42));
+    assert_eq!(rejection.category, ErrorCategory::Input);
+    assert_eq!(rejection.code, "unknown_transaction");
+    assert_eq!(
+        rejection.message,
+        "Invalid transaction_id 42 does not exist"
+    );
+}
+
+#[test]
+fn test_rejection_format_from_path() {
+    assert_eq!(RejectionFormat::from_path("out.csv"), RejectionFormat::Csv);
+    assert_eq!(
+        RejectionFormat::from_path("out.ndjson"),
+        RejectionFormat::Ndjson
+    );
+    assert_eq!(
+        RejectionFormat::from_path("out.JSONL"),
+        RejectionFormat::Ndjson
+    );
+    assert_eq!(RejectionFormat::from_path("out"), RejectionFormat::Csv);
+}
+
+fn sample_rejections() -> Vec<Rejection> {
+    let malformed = csv::ReaderBuilder::new()
+        .from_reader("type,client\nhello".as_bytes())
+        .records()
+        .next()
+        .unwrap()
+        .unwrap_err();
+
+    vec![
+        Rejection::malformed(
+            Origin {
+                source: "day1.csv".to_string(),
+                row: 3,
+                offset: 40,
+                raw: String::new(),
+            },
+            &RecordError::from(malformed),
+        ),
+        Rejection::engine(
+            Origin {
+                source: "day2.csv".to_string(),
+                row: 4,
+                offset: 52,
+                raw: "deposit,2,5,10".to_string(),
+            },
+            2,
+            5,
+            &EngineError::AccountLocked(2),
+        ),
+    ]
+}
+
+#[test]
+fn test_rejection_writer_csv() {
+    let mut buf = Vec::new();
+    {
+        let mut writer = RejectionWriter::new(&mut buf, RejectionFormat::Csv);
+        for rejection in sample_rejections() {
+            writer.write(&rejection).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+    let output = String::from_utf8(buf).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+
+    assert_eq!(lines[0], "source,row,raw,tx,client,category,code,message");
+    assert!(lines[1].starts_with(&format!("day1.csv,3,,,,input,{},", MALFORMED_RECORD)));
+    assert_eq!(
+        lines[2],
+        "day2.csv,4,\"deposit,2,5,10\",5,2,business,account_locked,Account locked: 2"
+    );
+}
+
+#[test]
+fn test_rejection_writer_ndjson() {
+    let mut buf = Vec::new();
+    {
+        let mut writer = RejectionWriter::new(&mut buf, RejectionFormat::Ndjson);
+        for rejection in sample_rejections() {
+            writer.write(&rejection).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+    let output = String::from_utf8(buf).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+
+    assert_eq!(lines.len(), 2);
+
+    let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(first["row"], 3);
+    assert_eq!(first["category"], "input");
+    assert_eq!(first["code"], MALFORMED_RECORD);
+    assert!(first["tx"].is_null());
+
+    let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+    assert_eq!(second["source"], "day2.csv");
+    assert_eq!(second["row"], 4);
+    assert_eq!(second["raw"], "deposit,2,5,10");
+    assert_eq!(second["tx"], 5);
+    assert_eq!(second["client"], 2);
+    assert_eq!(second["category"], "business");
+    assert_eq!(second["code"], "account_locked");
+}
+
+#[test]
+fn test_error_numbers_follow_categories() {
+    let errors = [
+        EngineError::AccountLocked(1),
+        EngineError::InsufficientFunds(1),
+        EngineError::NegativeBalance(1),
+        EngineError::DisputeWindowExpired(1),
+        EngineError::InvalidClient(1, 2),
+        EngineError::MissingAmount(1),
+        EngineError::MissingDestination(1),
+        EngineError::SelfTransfer(1),
+        EngineError::DuplicateTransaction(1),
+        EngineError::PrecisionExceeded(1, 4),
+        EngineError::MissingTimestamp(1),
+        EngineError::WalWrite(1, std::io::Error::other("disk full")),
+    ];
+
+    for err in &errors {
+        let expected = match err.number() / 100 {
+            1 => ErrorCategory::Business,
+            2 => ErrorCategory::Input,
+            _ => ErrorCategory::System,
+        };
+        assert_eq!(err.category(), expected, "{}", err.code());
+    }
+
+    assert_eq!(EngineError::InsufficientFunds(1).number(), 110);
+    assert_eq!(EngineError::MissingAmount(1).number(), 203);
+    assert_eq!(EngineError::MissingAmount(1).category().as_str(), "input");
+}
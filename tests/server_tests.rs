@@ -0,0 +1,185 @@
+use octopi::currency::Currency;
+use octopi::engine::Engine;
+use octopi::handle::EngineHandle;
+use octopi::policy::IngestPolicy;
+use octopi::server::serve;
+use octopi::transaction::Transaction;
+
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+
+async fn start_server() -> (SocketAddr, EngineHandle) {
+    let handle = EngineHandle::spawn(Engine::default(), 16);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(serve(listener, handle.clone(), IngestPolicy::default()));
+
+    (addr, handle)
+}
+
+struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: tokio::net::tcp::OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Self {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer.write_all(line.as_bytes()).await.unwrap();
+        self.writer.write_all(b"\n").await.unwrap();
+    }
+
+    async fn request(&mut self, line: &str) -> Value {
+        self.send(line).await;
+        let response = self.lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_server_accepts_csv_and_ndjson() {
+    let (addr, _handle) = start_server().await;
+    let mut client = Client::connect(addr).await;
+
+    let response = client.request("deposit, 1, 1, 100.0").await;
+    assert_eq!(response["status"], "ok");
+    assert_eq!(response["tx"], 1);
+
+    let response = client
+        .request(r#"{"type":"deposit","client":1,"tx":2,"amount":"2.5"}"#)
+        .await;
+    assert_eq!(response["status"], "ok");
+    assert_eq!(response["tx"], 2);
+
+    let response = client.request("account 1").await;
+    assert_eq!(response["status"], "ok");
+    assert_eq!(response["accounts"][0]["available"], "102.5");
+    assert_eq!(response["accounts"][0]["locked"], false);
+
+    let response = client.request("deposit,1,3,7,GBP").await;
+    assert_eq!(response["status"], "ok");
+
+    let response = client.request("account 1").await;
+    assert_eq!(response["accounts"][0]["currency"], "GBP");
+    assert_eq!(response["accounts"][0]["available"], "7");
+    assert_eq!(response["accounts"][1]["currency"], "USD");
+
+    // Timestamps follow `to` in the default header
+    let response = client.request("deposit,1,4,1,,,1700000000").await;
+    assert_eq!(response["status"], "ok");
+
+    let response = client.request("dispute,1,4,,,,1800000000").await;
+    assert_eq!(response["code"], "dispute_window_expired");
+}
+
+#[tokio::test]
+async fn test_server_reports_errors() {
+    let (addr, _handle) = start_server().await;
+    let mut client = Client::connect(addr).await;
+
+    client.request("deposit,1,1,100").await;
+
+    let response = client.request("deposit,1,1,100").await;
+    assert_eq!(response["status"], "error");
+    assert_eq!(response["code"], "duplicate_transaction");
+
+    let response = client.request("resolve,1,1,").await;
+    assert_eq!(response["code"], "not_disputed");
+
+    let response = client.request("withdrawal,1,2,1wds00.00").await;
+    assert_eq!(response["code"], "malformed_record");
+
+    let response = client.request("hello").await;
+    assert_eq!(response["code"], "unknown_request");
+
+    let response = client.request("account 9").await;
+    assert_eq!(response["code"], "unknown_client");
+
+    // The connection is still usable after errors
+    let response = client.request("withdrawal,1,2,40").await;
+    assert_eq!(response["status"], "ok");
+}
+
+#[tokio::test]
+async fn test_server_uses_connection_header() {
+    let (addr, _handle) = start_server().await;
+    let mut client = Client::connect(addr).await;
+
+    client.send("client,tx,amount,type").await;
+    let response = client.request("3,7,12.5,deposit").await;
+    assert_eq!(response["status"], "ok");
+
+    let response = client.request("account 3").await;
+    assert_eq!(response["accounts"][0]["total"], "12.5");
+}
+
+#[tokio::test]
+async fn test_server_shares_engine_between_connections() {
+    let (addr, handle) = start_server().await;
+
+    let mut tasks = Vec::new();
+    for client in 1..=8u16 {
+        tasks.push(tokio::spawn(async move {
+            let mut connection = Client::connect(addr).await;
+            for i in 0..25u32 {
+                let tx = client as u32 * 1000 + i;
+                let response = connection
+                    .request(&format!("deposit,{},{},1", client, tx))
+                    .await;
+                assert_eq!(response["status"], "ok");
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut client = Client::connect(addr).await;
+    let response = client.request("accounts").await;
+    let accounts = response["accounts"].as_array().unwrap();
+    assert_eq!(accounts.len(), 8);
+    for (i, account) in accounts.iter().enumerate() {
+        assert_eq!(account["client"], i as u64 + 1);
+        assert_eq!(account["total"], "25");
+    }
+
+    let engine = handle.shutdown().await.unwrap();
+    assert_eq!(engine.accounts().count(), 8);
+
+    // Once the engine is gone connections are told so
+    let response = client.request("deposit,1,1,1").await;
+    assert_eq!(response["code"], "engine_stopped");
+}
+
+#[tokio::test]
+async fn test_handle_applies_transactions() {
+    let handle = EngineHandle::spawn(Engine::default(), 1);
+
+    let result = handle
+        .apply(Transaction::new_deposit(1, 1, Decimal::from(10)))
+        .await
+        .unwrap();
+    assert!(result.is_ok());
+
+    let result = handle
+        .apply(Transaction::new_withdrawal(1, 2, Decimal::from(20)))
+        .await
+        .unwrap();
+    assert!(result.is_err());
+
+    let account = handle.account(1).await.unwrap().unwrap();
+    assert_eq!(account.balance(Currency::USD).available, Decimal::from(10));
+    assert!(handle.account(2).await.unwrap().is_none());
+}
@@ -0,0 +1,239 @@
+mod common;
+
+use common::dump;
+use octopi::engine::Engine;
+use octopi::policy::EnginePolicy;
+use octopi::rejection::Rejection;
+use octopi::sharded::ShardedEngine;
+use octopi::transaction::Transaction;
+use octopi::{stream_records, Origin};
+
+use rust_decimal::Decimal;
+use tokio::sync::mpsc;
+
+const DATA_FILES: &[&str] = &[
+    "integration_test_data/chargeback.csv",
+    "integration_test_data/decimals.csv",
+    "integration_test_data/dispute.csv",
+    "integration_test_data/error_bad_format.csv",
+    "integration_test_data/medium.csv",
+    "integration_test_data/multiple_accounts.csv",
+    "integration_test_data/resolve.csv",
+    "integration_test_data/transactions_1mb.csv",
+    "integration_test_data/transactions_stress_test.csv",
+];
+
+fn transactions(path: &str) -> Vec<(Origin, Transaction)> {
+    stream_records(path)
+        .unwrap()
+        .filter_map(|record| {
+            let tx = Transaction::try_from(record.result.ok()?).ok()?;
+            Some((record.origin, tx))
+        })
+        .collect()
+}
+
+fn run_single(txs: &[(Origin, Transaction)]) -> Engine {
+    let mut engine = Engine::default();
+    for (_, tx) in txs {
+        let _ = engine.apply_transaction(tx.clone());
+    }
+    engine
+}
+
+async fn run_sharded(txs: &[(Origin, Transaction)], shards: usize) -> Engine {
+    let mut engine = ShardedEngine::new(shards, 16, None);
+    for (origin, tx) in txs {
+        engine.send(origin.clone(), tx.clone()).await;
+    }
+    engine.finish().await
+}
+
+#[tokio::test]
+async fn test_sharded_matches_single_engine() {
+    for path in DATA_FILES {
+        let txs = transactions(path);
+        let expected = dump(&run_single(&txs));
+
+        for shards in [1, 2, 4, 7] {
+            let engine = run_sharded(&txs, shards).await;
+            assert_eq!(dump(&engine), expected, "{} with {} shards", path, shards);
+            assert!(
+                engine.verify_ledger().is_ok(),
+                "{} with {} shards",
+                path,
+                shards
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_sharded_rejects_cross_client_duplicate() {
+    let (rejections_tx, mut rejections_rx) = mpsc::channel::<Rejection>(16);
+    let mut engine = ShardedEngine::new(4, 16, Some(rejections_tx));
+
+    engine
+        .send(
+            Origin::default(),
+            Transaction::new_deposit(1, 1, Decimal::from(10)),
+        )
+        .await;
+    engine
+        .send(
+            Origin::default(),
+            Transaction::new_deposit(2, 1, Decimal::from(20)),
+        )
+        .await;
+    engine
+        .send(Origin::default(), Transaction::new_dispute(2, 1))
+        .await;
+
+    let engine = engine.finish().await;
+
+    assert_eq!(
+        dump(&engine),
+        "client,currency,available,held,total,locked\n1,USD,10,0,10,false\n"
+    );
+
+    let first = rejections_rx.recv().await.unwrap();
+    assert_eq!(first.client, Some(2));
+    assert_eq!(first.code, "duplicate_transaction");
+
+    let second = rejections_rx.recv().await.unwrap();
+    assert_eq!(second.client, Some(2));
+    assert_eq!(second.code, "invalid_client");
+
+    assert!(rejections_rx.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn test_sharded_rejected_transaction_does_not_claim_its_id() {
+    // Client 1 is locked by the chargeback, so its deposit of tx 5 is
+    // rejected and client 2 on the other shard may still use the id
+    let txs: Vec<(Origin, Transaction)> = [
+        Transaction::new_deposit(1, 1, Decimal::from(10)),
+        Transaction::new_dispute(1, 1),
+        Transaction::new_chargeback(1, 1),
+        Transaction::new_deposit(1, 5, Decimal::from(10)),
+        Transaction::new_deposit(2, 5, Decimal::from(7)),
+        Transaction::new_dispute(2, 5),
+    ]
+    .into_iter()
+    .map(|tx| (Origin::default(), tx))
+    .collect();
+
+    let expected = dump(&run_single(&txs));
+    assert!(expected.contains("2,USD,0,7,7,false"));
+
+    for shards in [2, 4] {
+        assert_eq!(dump(&run_sharded(&txs, shards).await), expected);
+    }
+}
+
+#[tokio::test]
+async fn test_sharded_finish_keeps_policy() {
+    let policy = EnginePolicy {
+        dispute_withdrawals: true,
+        ..EnginePolicy::default()
+    };
+    let engine = ShardedEngine::from_engine(Engine::default().with_policy(policy), 3, 16, None);
+
+    assert_eq!(engine.finish().await.policy(), policy);
+}
+
+#[tokio::test]
+async fn test_sharded_transfers_across_shards_match_single_engine() {
+    // With 2 shards clients 1 and 3 live on one, 2 and 4 on the other
+    let txs: Vec<(Origin, Transaction)> = [
+        Transaction::new_deposit(1, 1, Decimal::from(10)),
+        Transaction::new_transfer(1, 2, 2, Decimal::from(4)),
+        // Only possible once the transfer has landed
+        Transaction::new_withdrawal(2, 3, Decimal::from(3)),
+        Transaction::new_transfer(1, 2, 4, Decimal::from(100)), // insufficient funds
+        Transaction::new_transfer(2, 3, 5, Decimal::from(1)),
+        Transaction::new_transfer(3, 4, 6, Decimal::from(1)),
+        // Holds what is left of transfer 2 with client 2, which is nothing,
+        // and the chargeback locks client 2
+        Transaction::new_dispute(1, 2),
+        Transaction::new_withdrawal(2, 7, Decimal::from(1)), // insufficient funds
+        Transaction::new_chargeback(1, 2),
+        Transaction::new_transfer(1, 2, 8, Decimal::from(1)), // client 2 locked
+        Transaction::new_deposit(4, 9, Decimal::from(2)),
+        Transaction::new_transfer(4, 1, 10, Decimal::from(2)),
+        Transaction::new_dispute(3, 6),
+        Transaction::new_resolve(3, 6),
+        Transaction::new_deposit(2, 2, Decimal::from(1)), // duplicate id
+    ]
+    .into_iter()
+    .map(|tx| (Origin::default(), tx))
+    .collect();
+
+    let single = run_single(&txs);
+    let expected = dump(&single);
+    assert_eq!(
+        expected,
+        "client,currency,available,held,total,locked\n\
+         1,USD,8,0,8,false\n\
+         2,USD,0,0,0,true\n\
+         3,USD,0,0,0,false\n\
+         4,USD,1,0,1,false\n"
+    );
+
+    for shards in [2, 3, 4] {
+        let engine = run_sharded(&txs, shards).await;
+        assert_eq!(dump(&engine), expected, "{} shards", shards);
+        assert!(engine.verify_ledger().is_ok(), "{} shards", shards);
+
+        for client in 1..=4 {
+            let ids = |engine: &Engine| -> Vec<u32> {
+                engine
+                    .history(client)
+                    .map(|stored| stored.tx.tx_id)
+                    .collect()
+            };
+            assert_eq!(ids(&engine), ids(&single), "client {}", client);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_sharded_restored_transfer_across_shards_can_be_disputed() {
+    let mut restored = Engine::default();
+    for tx in [
+        Transaction::new_deposit(1, 1, Decimal::from(10)),
+        Transaction::new_transfer(1, 2, 2, Decimal::from(4)),
+    ] {
+        restored.apply_transaction(tx).unwrap();
+    }
+
+    let mut engine = ShardedEngine::from_engine(restored, 2, 16, None);
+    for tx in [
+        Transaction::new_dispute(1, 2),
+        Transaction::new_chargeback(1, 2),
+    ] {
+        engine.send(Origin::default(), tx).await;
+    }
+    let engine = engine.finish().await;
+
+    assert_eq!(
+        dump(&engine),
+        "client,currency,available,held,total,locked
+\
+         1,USD,10,0,10,false\n\
+         2,USD,0,0,0,true\n"
+    );
+}
+
+#[tokio::test]
+async fn test_shard_for_is_stable() {
+    let engine = ShardedEngine::new(3, 1, None);
+
+    assert_eq!(engine.shard_count(), 3);
+    for client in 0..100u16 {
+        assert_eq!(engine.shard_for(client), engine.shard_for(client));
+        assert!(engine.shard_for(client) < 3);
+    }
+
+    engine.finish().await;
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use common::{dump, snapshot};
+use octopi::admin::AdminEvent;
+use octopi::engine::Engine;
+use octopi::error::{EngineError, SnapshotError};
+use octopi::sharded::ShardedEngine;
+use octopi::snapshot::SNAPSHOT_VERSION;
+use octopi::transaction::{Transaction, TransactionState};
+use octopi::Origin;
+
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+fn yesterday() -> Engine {
+    let mut engine = Engine::default();
+    let txs = vec![
+        Transaction::new_deposit(1, 1, Decimal::from_str("100.1234").unwrap()),
+        Transaction::new_deposit(1, 2, Decimal::from(50)),
+        Transaction::new_dispute(1, 2),
+        Transaction::new_deposit(2, 3, Decimal::from(10)),
+        Transaction::new_dispute(2, 3),
+        Transaction::new_chargeback(2, 3),
+        Transaction::new_deposit(3, 4, Decimal::from(7)),
+    ];
+    for tx in txs {
+        engine.apply_transaction(tx).unwrap();
+    }
+    engine
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let engine = yesterday();
+    let bytes = snapshot(&engine);
+
+    let restored = Engine::restore(bytes.as_slice()).unwrap();
+
+    assert_eq!(dump(&restored), dump(&engine));
+    assert_eq!(snapshot(&restored), bytes);
+}
+
+#[test]
+fn test_snapshot_keeps_the_ledger() {
+    let engine = yesterday();
+    let restored = Engine::restore(snapshot(&engine).as_slice()).unwrap();
+
+    assert_eq!(restored.ledger(), engine.ledger());
+    assert!(restored.verify_ledger().is_ok());
+
+    // A snapshot whose balances were edited no longer adds up
+    let edited = String::from_utf8(snapshot(&engine))
+        .unwrap()
+        .replace("\"available\":\"7\"", "\"available\":\"70\"");
+    assert!(matches!(
+        Engine::restore(edited.as_bytes()),
+        Err(SnapshotError::Ledger(_))
+    ));
+}
+
+#[test]
+fn test_snapshot_keeps_the_admin_log() {
+    let mut engine = yesterday();
+    let events = [
+        AdminEvent::freeze(1, "jo", "suspected fraud"),
+        AdminEvent::unlock(1, "sam", "cleared"),
+    ];
+    for event in events.clone() {
+        engine.apply_admin(event).unwrap();
+    }
+
+    let restored = Engine::restore(snapshot(&engine).as_slice()).unwrap();
+    assert_eq!(restored.admin_log(1), events);
+    assert!(restored.admin_log(2).is_empty());
+    assert_eq!(snapshot(&restored), snapshot(&engine));
+}
+
+#[test]
+fn test_snapshot_is_versioned() {
+    let bytes = snapshot(&Engine::default());
+    let output = String::from_utf8(bytes).unwrap();
+
+    assert_eq!(
+        output,
+        format!("{{\"version\":{},\"wal_position\":0}}\n", SNAPSHOT_VERSION)
+    );
+}
+
+#[test]
+fn test_restored_transactions_remain_disputable() {
+    let mut engine = Engine::restore(snapshot(&yesterday()).as_slice()).unwrap();
+
+    // Yesterday's deposit can be disputed today
+    assert!(engine
+        .apply_transaction(Transaction::new_dispute(1, 1))
+        .is_ok());
+
+    // And dispute state carried over
+    let result = engine.apply_transaction(Transaction::new_dispute(1, 2));
+    assert!(matches!(result, Err(EngineError::AlreadyDisputed(2))));
+    assert!(engine
+        .apply_transaction(Transaction::new_resolve(1, 2))
+        .is_ok());
+
+    // Locked accounts stay locked
+    let result = engine.apply_transaction(Transaction::new_deposit(2, 5, Decimal::ONE));
+    assert!(matches!(result, Err(EngineError::AccountLocked(2))));
+
+    // Ids used yesterday cannot be reused
+    let result = engine.apply_transaction(Transaction::new_deposit(3, 4, Decimal::ONE));
+    assert!(matches!(result, Err(EngineError::DuplicateTransaction(4))));
+
+    let states: Vec<_> = engine
+        .transactions()
+        .filter(|stored| stored.tx.client == 1)
+        .map(|stored| (stored.tx.tx_id, stored.state))
+        .collect();
+    assert!(states.contains(&(1, TransactionState::Disputed)));
+    assert!(states.contains(&(2, TransactionState::Resolved)));
+}
+
+#[test]
+fn test_restore_rejects_unknown_version() {
+    let input = "{\"version\":999}\n";
+
+    let result = Engine::restore(input.as_bytes());
+    assert!(matches!(
+        result,
+        Err(SnapshotError::UnsupportedVersion(999))
+    ));
+}
+
+#[test]
+fn test_restore_rejects_empty_input() {
+    let result = Engine::restore("".as_bytes());
+    assert!(matches!(result, Err(SnapshotError::MissingHeader)));
+}
+
+#[test]
+fn test_restore_reports_bad_line() {
+    let input = format!(
+        "{{\"version\":{}}}\n{{\"type\":\"account\",\"client\":1}}\n",
+        SNAPSHOT_VERSION
+    );
+
+    let result = Engine::restore(input.as_bytes());
+    assert!(matches!(
+        result,
+        Err(SnapshotError::InvalidRecord { line: 2, .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_sharded_engine_resumes_from_snapshot() {
+    let restored = Engine::restore(snapshot(&yesterday()).as_slice()).unwrap();
+    let mut engine = ShardedEngine::from_engine(restored, 4, 16, None);
+
+    engine
+        .send(Origin::default(), Transaction::new_dispute(1, 1))
+        .await;
+    // Tx 4 belongs to client 3 so client 1 cannot reuse it, even on another shard
+    engine
+        .send(
+            Origin::default(),
+            Transaction::new_deposit(1, 4, Decimal::ONE),
+        )
+        .await;
+    engine
+        .send(
+            Origin::default(),
+            Transaction::new_deposit(3, 5, Decimal::ONE),
+        )
+        .await;
+
+    let engine = engine.finish().await;
+
+    assert_eq!(
+        dump(&engine),
+        "client,currency,available,held,total,locked\n\
+         1,USD,0.0000,150.1234,150.1234,false\n\
+         2,USD,0,0,0,true\n\
+         3,USD,8,0,8,false\n"
+    );
+}
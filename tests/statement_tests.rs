@@ -0,0 +1,216 @@
+use octopi::account::Balance;
+use octopi::currency::Currency;
+use octopi::engine::Engine;
+use octopi::output::OutputFormat;
+use octopi::statement::{Statement, StatementBuilder, StatementRange};
+use octopi::transaction::{Transaction, TransactionType};
+
+use rust_decimal::Decimal;
+
+fn transactions() -> Vec<(u64, Transaction)> {
+    [
+        Transaction::new_deposit(1, 1, Decimal::from(100)),
+        Transaction::new_deposit(2, 2, Decimal::from(50)),
+        Transaction::new_withdrawal(1, 3, Decimal::from(30)),
+        Transaction::new_withdrawal(1, 4, Decimal::from(500)),
+        Transaction::new_transfer(2, 1, 5, Decimal::from(20)),
+        Transaction::new_dispute(2, 5),
+        Transaction::new_deposit(1, 6, Decimal::from(7)).with_currency(Currency::EUR),
+        Transaction::new_chargeback(2, 5),
+    ]
+    .into_iter()
+    .zip(1..)
+    .map(|(tx, position)| (position, tx))
+    .collect()
+}
+
+fn balance(available: i64, held: i64) -> Balance {
+    Balance {
+        available: Decimal::from(available),
+        held: Decimal::from(held),
+        total: Decimal::from(available + held),
+    }
+}
+
+fn range(from: Option<u64>, to: Option<u64>) -> StatementRange {
+    StatementRange::Positions { from, to }
+}
+
+/// Deposits and withdrawals of client 1, the second without a timestamp and
+/// the fourth stamped earlier than the one before it.
+fn timed_transactions() -> Vec<(u64, Transaction)> {
+    [
+        Transaction::new_deposit(1, 1, Decimal::from(100)).with_timestamp(100),
+        Transaction::new_deposit(1, 2, Decimal::from(10)),
+        Transaction::new_withdrawal(1, 3, Decimal::from(30)).with_timestamp(200),
+        Transaction::new_deposit(1, 4, Decimal::from(5)).with_timestamp(150),
+        Transaction::new_withdrawal(1, 5, Decimal::from(1)).with_timestamp(300),
+        Transaction::new_deposit(1, 6, Decimal::from(1000)).with_timestamp(400),
+    ]
+    .into_iter()
+    .zip(1..)
+    .map(|(tx, position)| (position, tx))
+    .collect()
+}
+
+#[test]
+fn test_statement_reports_each_effect_in_range() {
+    let statement = Statement::replay(
+        Engine::default(),
+        1,
+        range(Some(2), Some(6)),
+        transactions(),
+    );
+
+    assert_eq!(statement.opening.len(), 1);
+    assert_eq!(statement.opening[&Currency::USD], balance(100, 0));
+
+    // The rejected withdrawal is left out, the dispute of the incoming
+    // transfer is on the statement of the client it holds funds from
+    let lines: Vec<(u64, TransactionType, Decimal, Decimal)> = statement
+        .lines
+        .iter()
+        .map(|line| (line.position, line.kind.clone(), line.available, line.held))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (
+                3,
+                TransactionType::Withdrawal,
+                Decimal::from(-30),
+                Decimal::ZERO
+            ),
+            (
+                5,
+                TransactionType::Transfer,
+                Decimal::from(20),
+                Decimal::ZERO
+            ),
+            (
+                6,
+                TransactionType::Dispute,
+                Decimal::from(-20),
+                Decimal::from(20)
+            ),
+        ]
+    );
+    assert_eq!(statement.lines[2].balance, balance(70, 20));
+
+    assert_eq!(statement.closing.len(), 1);
+    assert_eq!(statement.closing[&Currency::USD], balance(70, 20));
+}
+
+#[test]
+fn test_statement_without_range_covers_everything() {
+    let statement = Statement::replay(
+        Engine::default(),
+        1,
+        StatementRange::default(),
+        transactions(),
+    );
+
+    assert!(statement.opening.is_empty());
+    assert_eq!(statement.lines.len(), 6);
+    assert_eq!(statement.lines[4].currency, Currency::EUR);
+    assert_eq!(statement.closing[&Currency::USD], balance(70, 0));
+    assert_eq!(statement.closing[&Currency::EUR], balance(7, 0));
+}
+
+#[test]
+fn test_statement_range_past_the_input() {
+    let statement = Statement::replay(Engine::default(), 2, range(Some(20), None), transactions());
+
+    assert!(statement.lines.is_empty());
+    assert_eq!(statement.opening, statement.closing);
+    assert_eq!(statement.closing[&Currency::USD], balance(50, 0));
+}
+
+#[test]
+fn test_statement_time_range() {
+    let range = StatementRange::Time {
+        from: Some(200),
+        to: Some(300),
+    };
+    let mut builder = StatementBuilder::new(Engine::default(), 1, range);
+    for (position, tx) in timed_transactions() {
+        assert!(!builder.ends_before(position));
+        builder.replay(position, tx);
+    }
+    assert!(builder.ends_before(7));
+    let statement = builder.finish();
+
+    // The untimed deposit goes with the one before it, the late one with the
+    // withdrawal before it
+    assert_eq!(statement.opening[&Currency::USD], balance(110, 0));
+    let positions: Vec<u64> = statement.lines.iter().map(|line| line.position).collect();
+    assert_eq!(positions, [3, 4, 5]);
+    assert_eq!(statement.closing[&Currency::USD], balance(84, 0));
+}
+
+#[test]
+fn test_statement_time_range_open_at_the_start() {
+    let range = StatementRange::Time {
+        from: None,
+        to: Some(100),
+    };
+    let mut transactions = timed_transactions();
+    transactions.insert(0, (0, Transaction::new_deposit(1, 7, Decimal::from(1))));
+
+    // Rows before the first timestamp come before any time
+    let statement = Statement::replay(Engine::default(), 1, range, transactions.clone());
+    let positions: Vec<u64> = statement.lines.iter().map(|line| line.position).collect();
+    assert_eq!(positions, [0, 1, 2]);
+    assert!(statement.opening.is_empty());
+
+    // so a range with a start leaves them out
+    let range = StatementRange::Time {
+        from: Some(100),
+        to: Some(100),
+    };
+    let statement = Statement::replay(Engine::default(), 1, range, transactions);
+    assert_eq!(statement.opening[&Currency::USD], balance(1, 0));
+    assert_eq!(statement.lines.len(), 2);
+}
+
+#[test]
+fn test_statement_csv_output() {
+    let statement = Statement::replay(
+        Engine::default(),
+        1,
+        range(Some(3), Some(5)),
+        transactions(),
+    );
+
+    let mut buf = Vec::new();
+    statement.write(&mut buf, OutputFormat::Csv).unwrap();
+
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "line,position,tx,type,currency,available_change,held_change,available,held,total\n\
+         opening,,,,USD,,,100,0,100\n\
+         transaction,3,3,withdrawal,USD,-30,0,70,0,70\n\
+         transaction,5,5,transfer,USD,20,0,90,0,90\n\
+         closing,,,,USD,,,90,0,90\n"
+    );
+}
+
+#[test]
+fn test_statement_json_output() {
+    let statement = Statement::replay(Engine::default(), 2, range(None, Some(2)), transactions());
+
+    let mut buf = Vec::new();
+    statement.write(&mut buf, OutputFormat::Json).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+
+    assert_eq!(value["client"], 2);
+    assert_eq!(value["by"], "position");
+    assert_eq!(value["from"], serde_json::Value::Null);
+    assert_eq!(value["to"], 2);
+    assert_eq!(value["opening"], serde_json::json!([]));
+    assert_eq!(value["transactions"][0]["position"], 2);
+    assert_eq!(value["transactions"][0]["type"], "deposit");
+    assert_eq!(value["transactions"][0]["available_change"], "50");
+    assert_eq!(value["closing"][0]["currency"], "USD");
+    assert_eq!(value["closing"][0]["total"], "50");
+}
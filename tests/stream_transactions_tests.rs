@@ -1,4 +1,6 @@
-use octopi::stream_transactions;
+use octopi::error::RecordError;
+use octopi::input::InputFormat;
+use octopi::{expand_inputs, stream_records_from, stream_transactions};
 use rust_decimal::Decimal;
 use std::fs;
 use std::str::FromStr;
@@ -20,6 +22,7 @@ chargeback,1,5,1"#;
     // Test the function
     let txs: Vec<_> = stream_transactions(temp_file.path().to_str().unwrap())
         .unwrap()
+        .map(Result::unwrap)
         .collect();
 
     assert_eq!(txs.len(), 5);
@@ -46,6 +49,7 @@ fn test_stream_transactions_empty_csv() {
 
     let txs: Vec<_> = stream_transactions(temp_file.path().to_str().unwrap())
         .unwrap()
+        .map(Result::unwrap)
         .collect();
 
     assert_eq!(txs.len(), 0);
@@ -62,6 +66,7 @@ withdrawal,1,2,50.25"#;
 
     let txs: Vec<_> = stream_transactions(temp_file.path().to_str().unwrap())
         .unwrap()
+        .map(Result::unwrap)
         .collect();
 
     assert_eq!(txs.len(), 2);
@@ -95,6 +100,7 @@ fn test_stream_transactions_large_file() {
 
     let txs: Vec<_> = stream_transactions(temp_file.path().to_str().unwrap())
         .unwrap()
+        .map(Result::unwrap)
         .collect();
 
     assert_eq!(txs.len(), 100);
@@ -122,6 +128,7 @@ withdrawal,2,7,75.25"#;
 
     let txs: Vec<_> = stream_transactions(temp_file.path().to_str().unwrap())
         .unwrap()
+        .map(Result::unwrap)
         .collect();
 
     assert_eq!(txs.len(), 7);
@@ -147,32 +154,117 @@ deposit,1,1,100.00
 invalid_line,should,be,skipped
 withdrawal,1,2,25.50
 deposit,2,3,1wds00.00
-dispute,1,4,1"#;
+deposit,2,4
+dispute,1,4,"#;
 
     fs::write(&temp_file, csv_content).unwrap();
 
-    let txs: Vec<_> = stream_transactions(temp_file.path().to_str().unwrap())
+    let (txs, errors): (Vec<_>, Vec<_>) = stream_transactions(temp_file.path().to_str().unwrap())
         .unwrap()
-        .collect();
+        .partition(Result::is_ok);
+    let txs: Vec<_> = txs.into_iter().map(Result::unwrap).collect();
+    let errors: Vec<_> = errors.into_iter().map(Result::unwrap_err).collect();
 
-    // Should only have 3 valid transactions (deposit, withdrawal, dispute)
+    // The valid rows still come through, in order
     assert_eq!(txs.len(), 3);
+    assert_eq!(txs[0].tx, 1);
+    assert_eq!(txs[1].tx, 2);
+    assert_eq!(txs[2].tx, 4);
+    assert_eq!(txs[2].amount, None);
+
+    // Each bad row says where it is and what it was
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0].origin.row, 3);
+    assert_eq!(errors[0].origin.offset, 41);
+    assert_eq!(errors[0].origin.raw, "invalid_line,should,be,skipped");
+    assert_eq!(errors[1].origin.row, 5);
+    assert_eq!(errors[1].origin.raw, "deposit,2,3,1wds00.00");
+    assert_eq!(errors[2].origin.row, 6);
+    assert_eq!(errors[2].origin.raw, "deposit,2,4");
+    assert!(matches!(
+        errors[2].error,
+        RecordError::FieldCount {
+            expected: 4,
+            found: 3
+        }
+    ));
 
-    // Check first transaction (deposit)
-    let first_tx = &txs[0];
-    assert_eq!(first_tx.client, 1);
-    assert_eq!(first_tx.tx, 1);
-    assert_eq!(first_tx.amount, Some(Decimal::from_str("100.00").unwrap()));
+    assert_eq!(
+        errors[0].to_string().split(": ").next().unwrap(),
+        format!(
+            "Invalid record on line 3 of {} at byte 41",
+            temp_file.path().display()
+        )
+    );
+}
 
-    // Check second transaction (withdrawal)
-    let second_tx = &txs[1];
-    assert_eq!(second_tx.client, 1);
-    assert_eq!(second_tx.tx, 2);
-    assert_eq!(second_tx.amount, Some(Decimal::from_str("25.50").unwrap()));
+#[test]
+fn test_record_offsets() {
+    let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n";
+    let ndjson = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.0\"}\n\n\
+                  {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":\"2.0\"}\n";
+    let json = "[ {\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.0\"},\n  \
+                {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":\"2.0\"}]";
+
+    for (content, format) in [
+        (csv, InputFormat::Csv),
+        (ndjson, InputFormat::Ndjson),
+        (json, InputFormat::JsonArray),
+    ] {
+        let records: Vec<_> = stream_records_from(content.as_bytes(), "-", format)
+            .unwrap()
+            .collect();
+        assert_eq!(records.len(), 2);
+
+        // Every offset points at the start of its record
+        for (record, tx) in records.iter().zip([1, 2]) {
+            let rest = &content[record.origin.offset as usize..];
+            let expected = match format {
+                InputFormat::Csv => format!("deposit,1,{},", tx),
+                _ => format!("{{\"type\":\"deposit\",\"client\":1,\"tx\":{},", tx),
+            };
+            assert!(rest.starts_with(&expected), "{:?}: {}", format, rest);
+        }
+    }
+}
+
+#[test]
+fn test_stream_records_from_reader_tags_source() {
+    let csv_content = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n";
+
+    let records: Vec<_> = stream_records_from(csv_content.as_bytes(), "-", InputFormat::Csv)
+        .unwrap()
+        .collect();
+
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|record| record.origin.source == "-"));
+    assert_eq!(records[1].origin.row, 3);
+}
+
+#[test]
+fn test_expand_inputs() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["day2.csv", "day1.csv", "other.txt"] {
+        fs::write(dir.path().join(name), "type,client,tx,amount\n").unwrap();
+    }
+    let dir_path = dir.path().to_str().unwrap();
+
+    let inputs = expand_inputs(&[
+        "-".to_string(),
+        format!("{}/day*.csv", dir_path),
+        "plain.csv".to_string(),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        inputs,
+        [
+            "-".to_string(),
+            format!("{}/day1.csv", dir_path),
+            format!("{}/day2.csv", dir_path),
+            "plain.csv".to_string(),
+        ]
+    );
 
-    // Check third transaction (dispute)
-    let third_tx = &txs[2];
-    assert_eq!(third_tx.client, 1);
-    assert_eq!(third_tx.tx, 4);
-    assert_eq!(third_tx.amount, Some(Decimal::from(1)));
+    assert!(expand_inputs(&[format!("{}/missing*.csv", dir_path)]).is_err());
 }
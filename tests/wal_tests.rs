@@ -0,0 +1,439 @@
+mod common;
+
+use common::{dump, snapshot};
+use octopi::admin::AdminEvent;
+use octopi::engine::Engine;
+use octopi::error::{EngineError, WalError};
+use octopi::transaction::Transaction;
+use octopi::wal::{ResumeFilter, WalCursor};
+use octopi::Origin;
+
+use rust_decimal::Decimal;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use tempfile::tempdir;
+
+fn batch() -> Vec<Transaction> {
+    vec![
+        Transaction::new_deposit(1, 1, Decimal::from(100)),
+        Transaction::new_withdrawal(1, 2, Decimal::from(500)), // insufficient funds
+        Transaction::new_deposit(2, 3, Decimal::from(20)),
+        Transaction::new_dispute(2, 3),
+        Transaction::new_withdrawal(1, 4, Decimal::from(40)),
+        Transaction::new_resolve(1, 1), // not disputed
+    ]
+}
+
+#[test]
+fn test_wal_only_logs_accepted_transactions() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    for tx in batch() {
+        let _ = engine.apply_transaction(tx);
+    }
+
+    let log = fs::read_to_string(&path).unwrap();
+    let mut lines = log.lines();
+    assert_eq!(lines.next(), Some("{\"start\":0}"));
+    let tx_ids: Vec<u64> = lines
+        .map(|line| {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            value["tx_id"].as_u64().unwrap()
+        })
+        .collect();
+
+    assert_eq!(tx_ids, vec![1, 3, 3, 4]);
+}
+
+#[test]
+fn test_wal_replay_rebuilds_state() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    for tx in batch() {
+        let _ = engine.apply_transaction(tx);
+    }
+    let expected = dump(&engine);
+    drop(engine);
+
+    let mut recovered = Engine::default().with_wal(&path).unwrap();
+    assert_eq!(dump(&recovered), expected);
+
+    // Disputes carried over and new transactions keep being logged
+    let result = recovered.apply_transaction(Transaction::new_dispute(2, 3));
+    assert!(matches!(result, Err(EngineError::AlreadyDisputed(3))));
+    recovered
+        .apply_transaction(Transaction::new_resolve(2, 3))
+        .unwrap();
+    drop(recovered);
+
+    let recovered = Engine::default().with_wal(&path).unwrap();
+    assert!(dump(&recovered).contains("2,USD,20,0,20,false"));
+}
+
+/// Rows of an input file, each with the origin the reader would give it.
+fn rows(source: &str, txs: Vec<Transaction>) -> Vec<(Origin, Transaction)> {
+    txs.into_iter()
+        .zip(2..)
+        .map(|(tx, row)| {
+            let origin = Origin {
+                source: source.to_string(),
+                row,
+                offset: row * 20,
+                raw: String::new(),
+            };
+            (origin, tx)
+        })
+        .collect()
+}
+
+/// Feeds `inputs` to `engine` the way the file run does, skipping what the
+/// log already covers.
+fn try_run(
+    engine: &mut Engine,
+    inputs: &[(String, Vec<(Origin, Transaction)>)],
+) -> Result<(), WalError> {
+    let sources: Vec<String> = inputs.iter().map(|(source, _)| source.clone()).collect();
+    let mut resume = ResumeFilter::new(engine.resume_point().cloned(), &sources)?;
+
+    for (source, rows) in inputs {
+        if resume.skips_input(source)? {
+            continue;
+        }
+        for (origin, tx) in rows {
+            if !resume.skips(origin)? {
+                let _ = engine.apply_transaction_at(tx.clone(), origin);
+            }
+        }
+    }
+
+    resume.finish()
+}
+
+fn run(engine: &mut Engine, inputs: &[(String, Vec<(Origin, Transaction)>)]) {
+    try_run(engine, inputs).unwrap();
+}
+
+#[test]
+fn test_wal_rerun_after_crash_matches_a_run_without_log() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    // The withdrawal is rejected, but the deposit after it would cover it
+    let input = rows(
+        "input.csv",
+        vec![
+            Transaction::new_withdrawal(1, 1, Decimal::from(50)),
+            Transaction::new_deposit(1, 2, Decimal::from(100)),
+            Transaction::new_withdrawal(1, 3, Decimal::from(30)),
+        ],
+    );
+
+    // Crash after the deposit
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    let crashed = vec![("input.csv".to_string(), input[..2].to_vec())];
+    run(&mut engine, &crashed);
+    drop(engine);
+
+    // Start over with the whole input
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    assert_eq!(
+        engine.resume_point(),
+        Some(&WalCursor {
+            source: "input.csv".to_string(),
+            row: 3,
+            offset: 60
+        })
+    );
+    let whole = vec![("input.csv".to_string(), input)];
+    run(&mut engine, &whole);
+
+    let mut reference = Engine::default();
+    run(&mut reference, &whole);
+
+    assert_eq!(dump(&engine), dump(&reference));
+    assert!(dump(&engine).contains("1,USD,70,0,70,false"));
+}
+
+#[test]
+fn test_wal_rerun_skips_inputs_before_the_resume_point() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let inputs = vec![
+        (
+            "a.csv".to_string(),
+            rows(
+                "a.csv",
+                vec![Transaction::new_deposit(1, 1, Decimal::from(10))],
+            ),
+        ),
+        (
+            "b.csv".to_string(),
+            rows(
+                "b.csv",
+                vec![
+                    Transaction::new_deposit(1, 2, Decimal::from(5)),
+                    Transaction::new_withdrawal(1, 3, Decimal::from(12)),
+                ],
+            ),
+        ),
+        (
+            "c.csv".to_string(),
+            rows(
+                "c.csv",
+                vec![Transaction::new_withdrawal(1, 4, Decimal::from(1))],
+            ),
+        ),
+    ];
+
+    // Crash once all of b.csv is logged, so c.csv is read in full on rerun
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    run(&mut engine, &inputs[..2]);
+    drop(engine);
+
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    run(&mut engine, &inputs);
+
+    let mut reference = Engine::default();
+    run(&mut reference, &inputs);
+
+    assert_eq!(dump(&engine), dump(&reference));
+    assert!(dump(&engine).contains("1,USD,2,0,2,false"));
+}
+
+#[test]
+fn test_wal_resume_point_must_be_an_input() {
+    let cursor = WalCursor {
+        source: "old.csv".to_string(),
+        row: 7,
+        offset: 140,
+    };
+
+    let result = ResumeFilter::new(Some(cursor.clone()), &["new.csv".to_string()]);
+    assert!(matches!(
+        result,
+        Err(WalError::UnknownInput { ref input, row: 7 }) if input == "old.csv"
+    ));
+
+    // Which of the two the cursor points into cannot be told
+    let inputs = ["old.csv".to_string(), "old.csv".to_string()];
+    let result = ResumeFilter::new(Some(cursor), &inputs);
+    assert!(matches!(
+        result,
+        Err(WalError::DuplicateInput(ref input)) if input == "old.csv"
+    ));
+}
+
+#[test]
+fn test_wal_rerun_refuses_a_changed_input() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let txs = || {
+        vec![
+            Transaction::new_deposit(1, 1, Decimal::from(10)),
+            Transaction::new_deposit(1, 2, Decimal::from(5)),
+        ]
+    };
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    run(
+        &mut engine,
+        &[("input.csv".to_string(), rows("input.csv", txs()))],
+    );
+    drop(engine);
+
+    // A longer row earlier in the file moves the row the log stopped at
+    let mut moved = rows("input.csv", txs());
+    moved[1].0.offset += 3;
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    let result = try_run(&mut engine, &[("input.csv".to_string(), moved)]);
+    assert!(matches!(
+        result,
+        Err(WalError::InputChanged { ref input, row: 3 }) if input == "input.csv"
+    ));
+
+    // The file no longer reaches the row the log stopped at
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    let shorter = rows("input.csv", txs().into_iter().take(1).collect());
+    let result = try_run(&mut engine, &[("input.csv".to_string(), shorter)]);
+    assert!(matches!(result, Err(WalError::InputChanged { row: 3, .. })));
+}
+
+#[test]
+fn test_wal_truncate_empties_the_log() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let input = rows(
+        "input.csv",
+        vec![Transaction::new_deposit(1, 1, Decimal::from(10))],
+    );
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    run(&mut engine, &[("input.csv".to_string(), input)]);
+    let snapshot = snapshot(&engine);
+    engine.truncate_wal().unwrap();
+    assert!(engine.resume_point().is_none());
+
+    // Logging carries on where the snapshot left off
+    engine
+        .apply_transaction(Transaction::new_deposit(1, 2, Decimal::from(5)))
+        .unwrap();
+    drop(engine);
+
+    let log = fs::read_to_string(&path).unwrap();
+    assert_eq!(log.lines().next(), Some("{\"start\":1}"));
+    assert_eq!(log.lines().count(), 2);
+
+    let recovered = Engine::restore(snapshot.as_slice())
+        .unwrap()
+        .with_wal(&path)
+        .unwrap();
+    assert!(recovered.resume_point().is_none());
+    assert!(dump(&recovered).contains("1,USD,15,0,15,false"));
+
+    // Without the snapshot the log has nothing to follow
+    let result = Engine::default().with_wal(&path);
+    assert!(matches!(
+        result,
+        Err(WalError::StartsAhead {
+            start: 1,
+            position: 0
+        })
+    ));
+}
+
+#[test]
+fn test_wal_restarts_on_a_snapshot_taken_before_a_crash() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    for tx in batch() {
+        let _ = engine.apply_transaction(tx);
+    }
+    let expected = dump(&engine);
+
+    // Crash once the snapshot is saved, before the log is emptied
+    let snapshot = snapshot(&engine);
+    drop(engine);
+
+    let mut recovered = Engine::restore(snapshot.as_slice())
+        .unwrap()
+        .with_wal(&path)
+        .unwrap();
+    assert_eq!(dump(&recovered), expected);
+
+    // Records logged after the snapshot are still replayed on top of it
+    recovered
+        .apply_transaction(Transaction::new_resolve(2, 3))
+        .unwrap();
+    drop(recovered);
+
+    let recovered = Engine::restore(snapshot.as_slice())
+        .unwrap()
+        .with_wal(&path)
+        .unwrap();
+    assert!(dump(&recovered).contains("2,USD,20,0,20,false"));
+}
+
+#[test]
+fn test_wal_drops_torn_last_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    engine
+        .apply_transaction(Transaction::new_deposit(1, 1, Decimal::from(10)))
+        .unwrap();
+    drop(engine);
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"{\"client\":1,\"tx_id\":2,\"kin").unwrap();
+    drop(file);
+
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    assert!(dump(&engine).contains("1,USD,10,0,10,false"));
+
+    engine
+        .apply_transaction(Transaction::new_deposit(1, 2, Decimal::from(5)))
+        .unwrap();
+    drop(engine);
+
+    let log = fs::read_to_string(&path).unwrap();
+    assert_eq!(log.lines().count(), 3);
+    assert!(Engine::default().with_wal(&path).is_ok());
+}
+
+#[test]
+fn test_wal_rejects_corrupt_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+    fs::write(&path, "not json\n").unwrap();
+
+    let result = Engine::default().with_wal(&path);
+    assert!(matches!(
+        result,
+        Err(WalError::InvalidRecord { line: 1, .. })
+    ));
+}
+
+#[test]
+fn test_wal_replay_against_wrong_state() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    engine
+        .apply_transaction(Transaction::new_deposit(1, 1, Decimal::from(10)))
+        .unwrap();
+    drop(engine);
+
+    // An engine that already holds tx 1 cannot replay the log
+    let mut base = Engine::default();
+    base.apply_transaction(Transaction::new_deposit(1, 1, Decimal::from(10)))
+        .unwrap();
+
+    let result = base.with_wal(&path);
+    assert!(matches!(
+        result,
+        Err(WalError::Replay {
+            line: 2,
+            source: EngineError::DuplicateTransaction(1)
+        })
+    ));
+}
+
+#[test]
+fn test_wal_replays_admin_actions() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("engine.wal");
+
+    let mut engine = Engine::default().with_wal(&path).unwrap();
+    for tx in [
+        Transaction::new_deposit(1, 1, Decimal::from(10)),
+        Transaction::new_deposit(1, 2, Decimal::from(5)),
+        Transaction::new_dispute(1, 2),
+        Transaction::new_chargeback(1, 2),
+    ] {
+        engine.apply_transaction(tx).unwrap();
+    }
+    engine
+        .apply_admin(AdminEvent::unlock(1, "ops", "chargeback reversed by bank"))
+        .unwrap();
+    engine
+        .apply_transaction(Transaction::new_withdrawal(1, 3, Decimal::from(4)))
+        .unwrap();
+    drop(engine);
+
+    // The withdrawal only replays if the unlock before it does too
+    let recovered = Engine::default().with_wal(&path).unwrap();
+    let account = recovered.account(1).unwrap();
+    assert!(!account.locked);
+    assert_eq!(recovered.admin_log(1).len(), 1);
+    assert_eq!(recovered.admin_log(1)[0].operator, "ops");
+    assert!(dump(&recovered).contains("1,USD,6,0,6,false"));
+}